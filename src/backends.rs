@@ -0,0 +1,367 @@
+//! Backend profiles: per-vendor quirks of OpenAI-compatible servers
+//!
+//! A profile is selected by the `backend` option, else by the host of the
+//! `base_url` option, else by model-name prefix, falling back to plain OpenAI
+//! behaviour. Profiles adjust the finished request body and the parsed
+//! response; everything else is shared.
+
+use serde_json::{json, Value};
+
+use crate::options::RequestOptions;
+use crate::{ids, Choice};
+
+/// Quirks of one server family
+#[derive(Debug, PartialEq)]
+pub(crate) struct BackendProfile {
+    /// Name accepted by the `backend` option
+    pub name: &'static str,
+    /// Host suffixes served by this backend
+    hosts: &'static [&'static str],
+    /// Model-name prefixes that identify this backend
+    model_prefixes: &'static [&'static str],
+    /// Tool call ids must be exactly 9 alphanumeric characters
+    short_tool_call_ids: bool,
+    /// Assistant messages need string content, never `null`
+    string_content: bool,
+    /// Request fields the server rejects
+    unsupported_fields: &'static [&'static str],
+    /// Request fields the server knows under another name
+    renamed_fields: &'static [(&'static str, &'static str)],
+    /// Message fields the server rejects
+    unsupported_message_fields: &'static [&'static str],
+    /// `tool_choice` naming one function is not accepted; send only that
+    /// tool with `required` instead
+    function_choice_as_required: bool,
+    /// Reports `stop` instead of `tool_calls` when the reply calls tools
+    stop_with_tool_calls: bool,
+    /// Accepts assistant text next to `tool_calls` in history
+    content_with_tool_calls: bool,
+}
+
+impl BackendProfile {
+    const fn openai(name: &'static str) -> Self {
+        Self {
+            name,
+            hosts: &[],
+            model_prefixes: &[],
+            short_tool_call_ids: false,
+            string_content: false,
+            unsupported_fields: &[],
+            renamed_fields: &[],
+            unsupported_message_fields: &[],
+            function_choice_as_required: false,
+            stop_with_tool_calls: false,
+            content_with_tool_calls: true,
+        }
+    }
+
+    /// Adapt a finished Chat Completions body to this backend
+    pub(crate) fn shape_request(&self, body: &mut Value) {
+        if let Some(obj) = body.as_object_mut() {
+            for field in self.unsupported_fields {
+                obj.remove(*field);
+            }
+            for (from, to) in self.renamed_fields {
+                if let Some(value) = obj.remove(*from) {
+                    obj.insert(to.to_string(), value);
+                }
+            }
+        }
+
+        if self.function_choice_as_required {
+            if let Some(name) = body["tool_choice"]["function"]["name"].as_str() {
+                let name = name.to_string();
+                if let Some(tools) = body["tools"].as_array_mut() {
+                    tools.retain(|t| t["function"]["name"] == name.as_str());
+                }
+                body["tool_choice"] = json!("required");
+            }
+        }
+
+        let Some(messages) = body["messages"].as_array_mut() else {
+            return;
+        };
+        for msg in messages {
+            if let Some(obj) = msg.as_object_mut() {
+                for field in self.unsupported_message_fields {
+                    obj.remove(*field);
+                }
+            }
+
+            if self.string_content && msg["role"] == "assistant" && msg["content"].is_null() {
+                msg["content"] = json!("");
+            }
+
+            if self.short_tool_call_ids {
+                if let Some(id) = msg["tool_call_id"].as_str() {
+                    msg["tool_call_id"] = json!(short_id(id));
+                }
+                for call in msg["tool_calls"].as_array_mut().into_iter().flatten() {
+                    if let Some(id) = call["id"].as_str() {
+                        call["id"] = json!(short_id(id));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adapt a parsed response from this backend
+    pub(crate) fn shape_choice(&self, choice: &mut Choice) {
+        if self.stop_with_tool_calls
+            && choice.finish_reason.as_deref() == Some("stop")
+            && choice
+                .message
+                .tool_calls
+                .as_ref()
+                .is_some_and(|c| !c.is_empty())
+        {
+            choice.finish_reason = Some("tool_calls".to_string());
+        }
+    }
+
+    /// Whether a streamed `stop` should be reported as `tool_calls` once the
+    /// reply has called tools
+    pub(crate) fn stop_with_tool_calls(&self) -> bool {
+        self.stop_with_tool_calls
+    }
+
+    /// Whether assistant history may carry text next to `tool_calls`
+    pub(crate) fn content_with_tool_calls(&self) -> bool {
+        self.content_with_tool_calls
+    }
+}
+
+/// Known backends; the first entry is the default
+const PROFILES: &[BackendProfile] = &[
+    BackendProfile::openai("openai"),
+    BackendProfile {
+        hosts: &["api.mistral.ai"],
+        model_prefixes: &[
+            "mistral-",
+            "open-mistral",
+            "codestral",
+            "ministral",
+            "pixtral",
+            "magistral",
+            "devstral",
+        ],
+        short_tool_call_ids: true,
+        // Mistral validates strictly and has its own seed field
+        unsupported_fields: &["stream_options", "logit_bias", "user"],
+        renamed_fields: &[("seed", "random_seed")],
+        content_with_tool_calls: false,
+        ..BackendProfile::openai("mistral")
+    },
+    BackendProfile {
+        hosts: &["api.groq.com"],
+        unsupported_fields: &["logprobs", "top_logprobs", "logit_bias", "n"],
+        unsupported_message_fields: &["name", "reasoning_content"],
+        ..BackendProfile::openai("groq")
+    },
+    BackendProfile {
+        hosts: &["localhost:11434", "127.0.0.1:11434", "ollama.com"],
+        stop_with_tool_calls: true,
+        content_with_tool_calls: false,
+        ..BackendProfile::openai("ollama")
+    },
+    BackendProfile {
+        hosts: &["generativelanguage.googleapis.com"],
+        model_prefixes: &["gemini-"],
+        string_content: true,
+        function_choice_as_required: true,
+        ..BackendProfile::openai("gemini")
+    },
+];
+
+/// Profile for a request to `model` with `options`
+pub(crate) fn select(model: &str, options: &RequestOptions) -> &'static BackendProfile {
+    if let Some(profile) = options.backend.as_deref().and_then(by_name) {
+        return profile;
+    }
+
+    if let Some(base_url) = &options.base_url {
+        let host = host_and_port(base_url);
+        if let Some(profile) = PROFILES.iter().find(|p| {
+            p.hosts
+                .iter()
+                .any(|h| host == *h || host.ends_with(&format!(".{}", h)))
+        }) {
+            return profile;
+        }
+    }
+
+    let model = model.to_lowercase();
+    PROFILES
+        .iter()
+        .find(|p| {
+            p.model_prefixes
+                .iter()
+                .any(|prefix| model.starts_with(prefix))
+        })
+        .unwrap_or(&PROFILES[0])
+}
+
+/// Profile with the given name, if any
+pub(crate) fn by_name(name: &str) -> Option<&'static BackendProfile> {
+    PROFILES.iter().find(|p| p.name == name)
+}
+
+fn host_and_port(url: &str) -> String {
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    without_scheme
+        .split(['/', '?'])
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// Deterministic 9-character alphanumeric id, so a call and its result map
+/// to the same id; ids that already fit are kept
+fn short_id(id: &str) -> String {
+    if id.len() == 9 && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return id.to_string();
+    }
+
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let mut hash = ids::hash(&[id]);
+    (0..9)
+        .map(|_| {
+            let c = ALPHABET[(hash % ALPHABET.len() as u64) as usize] as char;
+            hash /= ALPHABET.len() as u64;
+            c
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseMessage;
+
+    fn options(json: &str) -> RequestOptions {
+        RequestOptions::parse(Some(json)).unwrap()
+    }
+
+    fn tool_turn() -> Value {
+        json!({
+            "model": "m",
+            "messages": [
+                { "role": "user", "content": "Weather?", "name": "ada" },
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_abc123def456", "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_abc123def456", "content": "sunny" }
+            ],
+            "tools": [
+                { "type": "function", "function": { "name": "get_weather" } },
+                { "type": "function", "function": { "name": "get_time" } }
+            ],
+            "tool_choice": { "type": "function", "function": { "name": "get_weather" } },
+            "stream_options": { "include_usage": true },
+            "seed": 7,
+            "logit_bias": { "50256": -100 },
+            "n": 1
+        })
+    }
+
+    #[test]
+    fn test_select() {
+        let none = RequestOptions::default();
+        assert_eq!(select("gpt-4o", &none).name, "openai");
+        assert_eq!(select("mistral-large-latest", &none).name, "mistral");
+        assert_eq!(select("gemini-2.5-flash", &none).name, "gemini");
+        assert_eq!(
+            select(
+                "llama-3.3-70b",
+                &options(r#"{"base_url":"https://api.groq.com/openai/v1"}"#)
+            )
+            .name,
+            "groq"
+        );
+        assert_eq!(
+            select(
+                "llama3.1",
+                &options(r#"{"base_url":"http://localhost:11434/v1"}"#)
+            )
+            .name,
+            "ollama"
+        );
+        // Explicit config wins over the model prefix
+        assert_eq!(
+            select("mistral-large", &options(r#"{"backend":"openai"}"#)).name,
+            "openai"
+        );
+    }
+
+    #[test]
+    fn test_mistral_request() {
+        let mut body = tool_turn();
+        by_name("mistral").unwrap().shape_request(&mut body);
+
+        let call_id = body["messages"][1]["tool_calls"][0]["id"].as_str().unwrap();
+        assert_eq!(call_id.len(), 9);
+        assert!(call_id.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(body["messages"][2]["tool_call_id"], call_id);
+        assert_eq!(body["random_seed"], 7);
+        assert!(body.get("seed").is_none());
+        assert!(body.get("stream_options").is_none());
+        assert_eq!(short_id("Ab3dE6gH9"), "Ab3dE6gH9");
+    }
+
+    #[test]
+    fn test_groq_request() {
+        let mut body = tool_turn();
+        by_name("groq").unwrap().shape_request(&mut body);
+
+        assert!(body.get("logit_bias").is_none());
+        assert!(body.get("n").is_none());
+        assert!(body["messages"][0].get("name").is_none());
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn test_gemini_request() {
+        let mut body = tool_turn();
+        by_name("gemini").unwrap().shape_request(&mut body);
+
+        assert_eq!(body["messages"][1]["content"], "");
+        assert_eq!(body["tool_choice"], "required");
+        assert_eq!(body["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            body["messages"][1]["tool_calls"][0]["id"],
+            "call_abc123def456"
+        );
+    }
+
+    #[test]
+    fn test_openai_request_unchanged() {
+        let mut body = tool_turn();
+        by_name("openai").unwrap().shape_request(&mut body);
+        assert_eq!(body, tool_turn());
+    }
+
+    #[test]
+    fn test_ollama_finish_reason() {
+        let mut choice = Choice {
+            message: ResponseMessage {
+                role: "assistant".to_string(),
+                tool_calls: Some(vec![crate::OpenAIToolCall {
+                    id: "call_1".to_string(),
+                    call_type: "function".to_string(),
+                    function: crate::FunctionCall {
+                        name: "get_weather".to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                }]),
+                ..ResponseMessage::default()
+            },
+            finish_reason: Some("stop".to_string()),
+        };
+        by_name("openai").unwrap().shape_choice(&mut choice);
+        assert_eq!(choice.finish_reason, Some("stop".to_string()));
+
+        by_name("ollama").unwrap().shape_choice(&mut choice);
+        assert_eq!(choice.finish_reason, Some("tool_calls".to_string()));
+    }
+}
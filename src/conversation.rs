@@ -0,0 +1,246 @@
+//! Conversation validation and repair
+//!
+//! Servers reject histories where a tool result has no matching tool call, a
+//! tool call has no result, or the same role appears twice in a row. This pass
+//! runs on converted OpenAI messages, either failing on the first problem
+//! (strict) or fixing it (repair):
+//! - missing tool results get a synthetic result
+//! - orphaned tool results are dropped
+//! - consecutive user or assistant messages are merged
+
+use serde_json::{json, Value};
+
+use crate::exports::abk::extension::provider::ProviderError;
+
+/// Content of the synthetic result inserted for an unanswered tool call
+const MISSING_RESULT: &str = "Tool result missing";
+
+/// Validate (`strict`) or repair converted messages. Each message is paired
+/// with the index of the InternalMessage it came from, which strict-mode
+/// errors report.
+pub(crate) fn validate(
+    messages: Vec<(usize, Value)>,
+    strict: bool,
+) -> Result<Vec<Value>, ProviderError> {
+    let mut out: Vec<Value> = Vec::new();
+    // Tool calls of the latest assistant message still waiting for a result
+    let mut pending: Vec<String> = Vec::new();
+    let mut pending_from = 0;
+
+    for (index, msg) in messages {
+        let role = msg["role"].as_str().unwrap_or("user").to_string();
+
+        if role == "tool" {
+            let id = msg["tool_call_id"].as_str().unwrap_or("");
+            match pending.iter().position(|p| p == id) {
+                Some(pos) => {
+                    pending.remove(pos);
+                    out.push(msg);
+                }
+                None if strict => {
+                    return Err(invalid_conversation(
+                        index,
+                        &format!("tool result '{}' does not match a pending tool call", id),
+                    ))
+                }
+                // Orphaned result: drop it
+                None => {}
+            }
+            continue;
+        }
+
+        close_pending(&mut out, &mut pending, pending_from, strict)?;
+
+        let last = out.last_mut().filter(|last| {
+            last["role"] == role.as_str() && (role == "user" || role == "assistant")
+        });
+        match last {
+            Some(_) if strict => {
+                return Err(invalid_conversation(
+                    index,
+                    &format!("consecutive {} messages", role),
+                ))
+            }
+            Some(last) => merge(last, msg),
+            None => out.push(msg),
+        }
+
+        if role == "assistant" {
+            pending = tool_call_ids(out.last().unwrap_or(&Value::Null));
+            pending_from = index;
+        }
+    }
+
+    close_pending(&mut out, &mut pending, pending_from, strict)?;
+    Ok(out)
+}
+
+/// Answer every still-pending tool call with a synthetic result
+fn close_pending(
+    out: &mut Vec<Value>,
+    pending: &mut Vec<String>,
+    pending_from: usize,
+    strict: bool,
+) -> Result<(), ProviderError> {
+    if let Some(id) = pending.first().filter(|_| strict) {
+        return Err(invalid_conversation(
+            pending_from,
+            &format!("tool call '{}' has no result", id),
+        ));
+    }
+    for id in pending.drain(..) {
+        out.push(json!({
+            "role": "tool",
+            "tool_call_id": id,
+            "content": MISSING_RESULT
+        }));
+    }
+    Ok(())
+}
+
+fn tool_call_ids(msg: &Value) -> Vec<String> {
+    msg["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|call| call["id"].as_str().map(str::to_string))
+        .collect()
+}
+
+/// Fold `next` into `last` (same role): text is joined, content parts and
+/// tool calls are concatenated
+fn merge(last: &mut Value, next: Value) {
+    last["content"] = merge_content(&last["content"], &next["content"]);
+
+    for key in ["reasoning_content", "reasoning"] {
+        if let Some(text) = next[key].as_str() {
+            last[key] = match last[key].as_str() {
+                Some(existing) => json!(format!("{}\n\n{}", existing, text)),
+                None => json!(text),
+            };
+        }
+    }
+
+    if let Some(calls) = next["tool_calls"].as_array() {
+        let mut merged = last["tool_calls"].as_array().cloned().unwrap_or_default();
+        merged.extend(calls.iter().cloned());
+        last["tool_calls"] = json!(merged);
+    }
+}
+
+fn merge_content(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Null, other) | (other, Value::Null) => other.clone(),
+        (Value::String(a), Value::String(b)) => json!(format!("{}\n\n{}", a, b)),
+        _ => {
+            let mut parts = content_parts(a);
+            parts.extend(content_parts(b));
+            json!(parts)
+        }
+    }
+}
+
+fn content_parts(content: &Value) -> Vec<Value> {
+    match content {
+        Value::Array(parts) => parts.clone(),
+        Value::String(text) => vec![json!({ "type": "text", "text": text })],
+        _ => Vec::new(),
+    }
+}
+
+fn invalid_conversation(index: usize, reason: &str) -> ProviderError {
+    ProviderError {
+        message: format!("Invalid conversation at message {}: {}", index, reason),
+        code: Some("INVALID_CONVERSATION".to_string()),
+        http_status: None,
+        response_body: None,
+        is_retryable: Some(false),
+        retry_after: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assistant_call(id: &str) -> Value {
+        json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": id,
+                "type": "function",
+                "function": { "name": "search", "arguments": "{}" }
+            }]
+        })
+    }
+
+    fn tool_result(id: &str) -> Value {
+        json!({ "role": "tool", "tool_call_id": id, "content": "ok" })
+    }
+
+    fn indexed(messages: Vec<Value>) -> Vec<(usize, Value)> {
+        messages.into_iter().enumerate().collect()
+    }
+
+    #[test]
+    fn test_valid_conversation_unchanged() {
+        let messages = vec![
+            json!({ "role": "system", "content": "Be brief." }),
+            json!({ "role": "user", "content": "Hi" }),
+            assistant_call("call_1"),
+            tool_result("call_1"),
+            json!({ "role": "assistant", "content": "Done." }),
+        ];
+        let repaired = validate(indexed(messages.clone()), false).unwrap();
+        assert_eq!(repaired, messages);
+        assert!(validate(indexed(messages), true).is_ok());
+    }
+
+    #[test]
+    fn test_repair() {
+        let messages = vec![
+            json!({ "role": "user", "content": "Hi" }),
+            json!({ "role": "user", "content": "Search please" }),
+            assistant_call("call_1"),
+            tool_result("call_9"),
+            json!({ "role": "user", "content": "Well?" }),
+        ];
+        let repaired = validate(indexed(messages), false).unwrap();
+
+        assert_eq!(repaired.len(), 4);
+        assert_eq!(repaired[0]["content"], "Hi\n\nSearch please");
+        assert_eq!(repaired[2]["tool_call_id"], "call_1");
+        assert_eq!(repaired[2]["content"], MISSING_RESULT);
+        assert_eq!(repaired[3]["content"], "Well?");
+    }
+
+    #[test]
+    fn test_strict_names_message_index() {
+        let orphan = vec![
+            json!({ "role": "user", "content": "Hi" }),
+            tool_result("call_1"),
+        ];
+        let err = validate(indexed(orphan), true).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_CONVERSATION".to_string()));
+        assert!(err.message.contains("message 1"));
+
+        let unanswered = vec![
+            json!({ "role": "user", "content": "Hi" }),
+            assistant_call("call_1"),
+            json!({ "role": "user", "content": "Well?" }),
+        ];
+        let err = validate(indexed(unanswered), true).unwrap_err();
+        assert!(err.message.contains("message 1"));
+        assert!(err.message.contains("'call_1' has no result"));
+
+        let doubled = vec![
+            json!({ "role": "assistant", "content": "A" }),
+            json!({ "role": "assistant", "content": "B" }),
+        ];
+        let err = validate(indexed(doubled), true).unwrap_err();
+        assert!(err
+            .message
+            .contains("message 1: consecutive assistant messages"));
+    }
+}
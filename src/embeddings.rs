@@ -0,0 +1,226 @@
+//! Embeddings requests and responses
+//!
+//! `/v1/embeddings` takes a list of inputs and returns one vector per input,
+//! either as a float array or, with `encoding_format: base64`, as the
+//! little-endian `f32` bytes in base64 (a third of the JSON size).
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::errors;
+use crate::exports::abk::extension::provider::{
+    EmbeddingsResponse as WitEmbeddingsResponse, ProviderError,
+};
+use crate::options::invalid_parameter;
+use crate::{wit_usage, OpenAIUsage};
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsBody {
+    #[serde(default)]
+    model: Option<String>,
+    data: Vec<EmbeddingItem>,
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingItem {
+    #[serde(default)]
+    index: usize,
+    embedding: Value,
+}
+
+/// Embeddings request body for `inputs`
+pub(crate) fn format_request(
+    inputs: &[String],
+    model: &str,
+    dimensions: Option<u32>,
+    encoding_format: Option<&str>,
+) -> Result<String, ProviderError> {
+    if inputs.is_empty() {
+        return Err(invalid_parameter("inputs", "must not be empty"));
+    }
+    if dimensions == Some(0) {
+        return Err(invalid_parameter("dimensions", "must be positive"));
+    }
+    if let Some(format) = encoding_format.filter(|f| !matches!(*f, "float" | "base64")) {
+        return Err(invalid_parameter(
+            "encoding_format",
+            &format!("expected float or base64, got '{}'", format),
+        ));
+    }
+
+    let mut body = json!({
+        "model": model,
+        "input": inputs
+    });
+    if let Some(dimensions) = dimensions {
+        body["dimensions"] = json!(dimensions);
+    }
+    if let Some(format) = encoding_format {
+        body["encoding_format"] = json!(format);
+    }
+
+    serde_json::to_string(&body).map_err(|e| ProviderError {
+        message: format!("Failed to serialize embeddings request: {}", e),
+        code: Some("SERIALIZATION_ERROR".to_string()),
+        http_status: None,
+        response_body: None,
+        is_retryable: Some(false),
+        retry_after: None,
+    })
+}
+
+/// Parse an embeddings response; vectors are returned in input order
+pub(crate) fn parse_response(body: &str) -> Result<WitEmbeddingsResponse, ProviderError> {
+    let parse_error = |message: String| ProviderError {
+        message,
+        code: Some("PARSE_ERROR".to_string()),
+        http_status: None,
+        response_body: Some(body.to_string()),
+        is_retryable: Some(false),
+        retry_after: None,
+    };
+
+    if let Ok(json) = serde_json::from_str::<Value>(body) {
+        if errors::is_error_body(&json) {
+            return Err(errors::classify(None, &[], body));
+        }
+    }
+
+    let mut response: EmbeddingsBody = serde_json::from_str(body)
+        .map_err(|e| parse_error(format!("Failed to parse embeddings response: {}", e)))?;
+    response.data.sort_by_key(|item| item.index);
+
+    let embeddings = response
+        .data
+        .iter()
+        .map(|item| match &item.embedding {
+            Value::String(encoded) => decode_floats(encoded).ok_or_else(|| {
+                parse_error(format!("Invalid base64 embedding at index {}", item.index))
+            }),
+            Value::Array(values) => values
+                .iter()
+                .map(|v| v.as_f64().map(|f| f as f32))
+                .collect::<Option<Vec<f32>>>()
+                .ok_or_else(|| {
+                    parse_error(format!("Non-numeric embedding at index {}", item.index))
+                }),
+            _ => Err(parse_error(format!(
+                "Missing embedding at index {}",
+                item.index
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(WitEmbeddingsResponse {
+        model: response.model,
+        embeddings,
+        usage: response.usage.map(wit_usage),
+    })
+}
+
+/// Decode base64 little-endian `f32`s
+fn decode_floats(encoded: &str) -> Option<Vec<f32>> {
+    let bytes = decode_base64(encoded)?;
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    )
+}
+
+/// Standard base64 (padding optional, whitespace ignored)
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let digits: Vec<u8> = encoded
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    let padded = digits.len().is_multiple_of(4);
+    let digits = match digits.iter().position(|&c| c == b'=') {
+        Some(pad) if padded && digits[pad..].iter().all(|&c| c == b'=') => &digits[..pad],
+        Some(_) => return None,
+        None => &digits[..],
+    };
+    if digits.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for group in digits.chunks(4) {
+        let mut bits = 0u32;
+        for &c in group {
+            bits = (bits << 6) | value(c)?;
+        }
+        bits <<= 6 * (4 - group.len() as u32);
+        let decoded = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        bytes.extend_from_slice(&decoded[..group.len() - 1]);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_request() {
+        let body = format_request(
+            &["fn main() {}".to_string()],
+            "text-embedding-3-small",
+            Some(256),
+            Some("base64"),
+        )
+        .unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["input"], json!(["fn main() {}"]));
+        assert_eq!(body["dimensions"], 256);
+        assert_eq!(body["encoding_format"], "base64");
+
+        let err = format_request(&[], "m", None, None).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_PARAMETER".to_string()));
+        assert!(format_request(&["x".to_string()], "m", None, Some("int8")).is_err());
+    }
+
+    #[test]
+    fn test_parse_float_and_base64() {
+        // [1.0, -2.5] as little-endian f32
+        let encoded = "AACAPwAAIMA=";
+        assert_eq!(decode_floats(encoded), Some(vec![1.0, -2.5]));
+        assert_eq!(decode_base64("AACAPwAAIMA"), decode_base64(encoded));
+        assert_eq!(decode_base64("TWE="), Some(b"Ma".to_vec()));
+        assert_eq!(decode_base64("A=A="), None);
+
+        let body = json!({
+            "object": "list",
+            "model": "text-embedding-3-small",
+            "data": [
+                { "object": "embedding", "index": 1, "embedding": encoded },
+                { "object": "embedding", "index": 0, "embedding": [0.5, 0.25] }
+            ],
+            "usage": { "prompt_tokens": 6, "total_tokens": 6 }
+        });
+        let parsed = parse_response(&body.to_string()).unwrap();
+        assert_eq!(parsed.embeddings, vec![vec![0.5, 0.25], vec![1.0, -2.5]]);
+        assert_eq!(parsed.model, Some("text-embedding-3-small".to_string()));
+        assert_eq!(parsed.usage.unwrap().prompt_tokens, 6);
+
+        let err = parse_response(r#"{"error":{"message":"Bad key","code":"invalid_api_key"}}"#)
+            .unwrap_err();
+        assert_eq!(err.code, Some("AUTHENTICATION_ERROR".to_string()));
+    }
+}
@@ -0,0 +1,426 @@
+//! Endpoint URLs and request headers
+//!
+//! Plain OpenAI-compatible servers take `{base}/chat/completions` with Bearer
+//! auth. Azure OpenAI routes by deployment
+//! (`{resource}/openai/deployments/{deployment}/chat/completions?api-version=...`)
+//! and authenticates with an `api-key` header. Responses API requests go to
+//! `{base}/responses`, or `{resource}/openai/v1/responses` on Azure, and
+//! embeddings requests to `{base}/embeddings` (deployment-scoped on Azure).
+//! Model listing uses `{base}/models`.
+//!
+//! Only standard headers are produced. GitHub Copilot-specific headers are
+//! never sent, not even when a host passes them through `extra_headers`.
+
+use crate::options::RequestOptions;
+
+/// GA Azure OpenAI data-plane API version used when none is configured
+pub(crate) const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
+
+/// GitHub Copilot headers this provider must never send
+const FORBIDDEN_HEADERS: &[&str] = &[
+    "x-request-id",
+    "x-initiator",
+    "x-interaction-id",
+    "x-interaction-type",
+    "copilot-integration-id",
+    "copilot-vision-request",
+    "editor-version",
+    "editor-plugin-version",
+    "openai-intent",
+    "x-github-api-version",
+];
+
+/// Full chat completions (or responses) URL for `model`
+pub(crate) fn api_url(base_url: &str, model: &str, options: &RequestOptions) -> String {
+    let path = if options.uses_responses_api(model) {
+        "responses"
+    } else {
+        "chat/completions"
+    };
+    url(base_url, model, path, options)
+}
+
+/// Full embeddings URL for `model`
+pub(crate) fn embeddings_url(base_url: &str, model: &str, options: &RequestOptions) -> String {
+    url(base_url, model, "embeddings", options)
+}
+
+/// URL listing the models the server serves
+pub(crate) fn models_url(base_url: &str, options: &RequestOptions) -> String {
+    url(base_url, "", "models", options)
+}
+
+/// URL of the endpoint `path`, deployment-scoped on Azure except for the
+/// Responses API and model listing
+fn url(base_url: &str, model: &str, path: &str, options: &RequestOptions) -> String {
+    let (base, query) = split_query(base_url);
+    let base = base.trim_end_matches('/');
+
+    if !is_azure(base_url, options) {
+        return match query {
+            Some(query) => format!("{}/{}?{}", base, path, query),
+            None => format!("{}/{}", base, path),
+        };
+    }
+
+    // The Azure v1 Responses endpoint is not deployment-scoped; the body's
+    // model names the deployment
+    if path == "responses" {
+        return format!("{}/openai/v1/responses", azure_resource(base));
+    }
+
+    let api_version = query
+        .and_then(|q| {
+            q.split('&')
+                .find_map(|pair| pair.strip_prefix("api-version="))
+        })
+        .map(str::to_string)
+        .or_else(|| options.azure_api_version.clone())
+        .unwrap_or_else(|| DEFAULT_AZURE_API_VERSION.to_string());
+
+    if path == "models" {
+        return format!(
+            "{}/openai/models?api-version={}",
+            azure_resource(base),
+            api_version
+        );
+    }
+
+    // A base URL that already names a deployment is used as-is
+    let deployment_base = if base.contains("/openai/deployments/") {
+        base.to_string()
+    } else {
+        format!(
+            "{}/openai/deployments/{}",
+            azure_resource(base),
+            azure_deployment(model, options)
+        )
+    };
+
+    format!("{}/{}?api-version={}", deployment_base, path, api_version)
+}
+
+/// Scheme and host of an Azure base URL, plus any path before its `/openai`
+/// segment (resource names may themselves start with "openai")
+fn azure_resource(base: &str) -> &str {
+    let host_start = base.find("://").map_or(0, |i| i + 3);
+    let path_start = base[host_start..]
+        .find('/')
+        .map_or(base.len(), |i| host_start + i);
+    let path = &base[path_start..];
+    let end = path
+        .find("/openai/")
+        .or_else(|| {
+            path.ends_with("/openai")
+                .then(|| path.len() - "/openai".len())
+        })
+        .unwrap_or(path.len());
+    &base[..path_start + end]
+}
+
+/// HTTP headers for a request to `base_url`
+pub(crate) fn request_headers(
+    base_url: &str,
+    api_key: &str,
+    streaming: bool,
+    options: &RequestOptions,
+) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+
+    // Local servers often run without a key; send no auth header at all then
+    if !api_key.is_empty() {
+        if is_azure(base_url, options) {
+            headers.push(("api-key".to_string(), api_key.to_string()));
+        } else {
+            headers.push(("Authorization".to_string(), format!("Bearer {}", api_key)));
+        }
+    }
+
+    headers.push(("Content-Type".to_string(), "application/json".to_string()));
+
+    if streaming {
+        headers.push(("Accept".to_string(), "text/event-stream".to_string()));
+    }
+
+    if let Some(organization) = &options.organization {
+        headers.push(("OpenAI-Organization".to_string(), organization.clone()));
+    }
+    if let Some(project) = &options.project {
+        headers.push(("OpenAI-Project".to_string(), project.clone()));
+    }
+
+    // OpenRouter app attribution
+    if host(base_url).ends_with("openrouter.ai") {
+        if let Some(app_url) = &options.app_url {
+            headers.push(("HTTP-Referer".to_string(), app_url.clone()));
+        }
+        if let Some(app_name) = &options.app_name {
+            headers.push(("X-Title".to_string(), app_name.clone()));
+        }
+    }
+
+    // Extra headers replace built-in ones with the same name
+    for (name, value) in &options.extra_headers {
+        headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        headers.push((name.clone(), value.clone()));
+    }
+
+    // Options validation already rejects these; keep the guarantee local too
+    headers.retain(|(name, _)| !is_forbidden_header(name));
+    headers
+}
+
+/// Whether `name` is a GitHub Copilot header
+pub(crate) fn is_forbidden_header(name: &str) -> bool {
+    FORBIDDEN_HEADERS.contains(&name.to_lowercase().as_str())
+}
+
+/// Whether `base_url` points at Azure OpenAI. The `azure` option overrides
+/// detection for custom domains and gateways.
+pub(crate) fn is_azure(base_url: &str, options: &RequestOptions) -> bool {
+    if let Some(azure) = options.azure {
+        return azure;
+    }
+    let host = host(base_url);
+    host.ends_with(".openai.azure.com") || host.ends_with(".cognitiveservices.azure.com")
+}
+
+/// Deployment for `model`: the `azure_deployments` mapping, else the model
+/// name itself (an `azure/` prefix is dropped)
+pub(crate) fn azure_deployment<'a>(model: &'a str, options: &'a RequestOptions) -> &'a str {
+    options
+        .azure_deployments
+        .get(model)
+        .map(String::as_str)
+        .unwrap_or_else(|| model.strip_prefix("azure/").unwrap_or(model))
+}
+
+fn split_query(url: &str) -> (&str, Option<&str>) {
+    match url.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (url, None),
+    }
+}
+
+fn host(url: &str) -> String {
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    without_scheme
+        .split(['/', '?', ':'])
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(json: &str) -> RequestOptions {
+        RequestOptions::parse(Some(json)).unwrap()
+    }
+
+    #[test]
+    fn test_plain_url() {
+        let url = api_url(
+            "http://localhost:11434/v1/",
+            "llama3",
+            &RequestOptions::default(),
+        );
+        assert_eq!(url, "http://localhost:11434/v1/chat/completions");
+    }
+
+    #[test]
+    fn test_azure_url_from_model() {
+        let url = api_url(
+            "https://my-resource.openai.azure.com",
+            "azure/gpt-4o-prod",
+            &RequestOptions::default(),
+        );
+        assert_eq!(
+            url,
+            "https://my-resource.openai.azure.com/openai/deployments/gpt-4o-prod/chat/completions?api-version=2024-10-21"
+        );
+    }
+
+    #[test]
+    fn test_azure_url_with_mapping_and_version() {
+        let opts = options(
+            r#"{"azure_api_version":"2025-01-01-preview","azure_deployments":{"gpt-4o":"chat-prod"}}"#,
+        );
+        let url = api_url(
+            "https://my-resource.openai.azure.com/openai/",
+            "gpt-4o",
+            &opts,
+        );
+        assert_eq!(
+            url,
+            "https://my-resource.openai.azure.com/openai/deployments/chat-prod/chat/completions?api-version=2025-01-01-preview"
+        );
+
+        // An api-version in the base URL wins over the option
+        let url = api_url(
+            "https://my-resource.openai.azure.com/openai/deployments/fixed?api-version=2024-06-01",
+            "gpt-4o",
+            &opts,
+        );
+        assert_eq!(
+            url,
+            "https://my-resource.openai.azure.com/openai/deployments/fixed/chat/completions?api-version=2024-06-01"
+        );
+    }
+
+    #[test]
+    fn test_responses_url() {
+        let url = api_url(
+            "https://api.openai.com/v1",
+            "o3-pro",
+            &RequestOptions::default(),
+        );
+        assert_eq!(url, "https://api.openai.com/v1/responses");
+
+        let url = api_url(
+            "https://api.openai.com/v1",
+            "gpt-4o",
+            &options(r#"{"api":"responses"}"#),
+        );
+        assert_eq!(url, "https://api.openai.com/v1/responses");
+
+        let url = api_url(
+            "https://my-resource.openai.azure.com/openai",
+            "codex-mini",
+            &RequestOptions::default(),
+        );
+        assert_eq!(
+            url,
+            "https://my-resource.openai.azure.com/openai/v1/responses"
+        );
+    }
+
+    #[test]
+    fn test_azure_resource_named_openai() {
+        let none = RequestOptions::default();
+        let base = "https://openai-prod.openai.azure.com/openai/deployments/x";
+        assert_eq!(
+            api_url(base, "codex-mini", &none),
+            "https://openai-prod.openai.azure.com/openai/v1/responses"
+        );
+        assert_eq!(
+            models_url(base, &none),
+            "https://openai-prod.openai.azure.com/openai/models?api-version=2024-10-21"
+        );
+        assert_eq!(
+            api_url("https://openai-prod.openai.azure.com/openai", "gpt-4o", &none),
+            "https://openai-prod.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+    }
+
+    #[test]
+    fn test_embeddings_url() {
+        let url = embeddings_url(
+            "http://localhost:11434/v1/",
+            "nomic-embed-text",
+            &RequestOptions::default(),
+        );
+        assert_eq!(url, "http://localhost:11434/v1/embeddings");
+
+        let url = embeddings_url(
+            "https://my-resource.openai.azure.com",
+            "text-embedding-3-small",
+            &RequestOptions::default(),
+        );
+        assert_eq!(
+            url,
+            "https://my-resource.openai.azure.com/openai/deployments/text-embedding-3-small/embeddings?api-version=2024-10-21"
+        );
+    }
+
+    #[test]
+    fn test_models_url() {
+        let none = RequestOptions::default();
+        assert_eq!(
+            models_url("http://localhost:8000/v1", &none),
+            "http://localhost:8000/v1/models"
+        );
+        assert_eq!(
+            models_url(
+                "https://my-resource.openai.azure.com/openai/deployments/gpt-4o",
+                &none
+            ),
+            "https://my-resource.openai.azure.com/openai/models?api-version=2024-10-21"
+        );
+    }
+
+    #[test]
+    fn test_headers() {
+        let headers = request_headers(
+            "https://my-resource.openai.azure.com",
+            "secret",
+            true,
+            &RequestOptions::default(),
+        );
+        assert_eq!(headers[0], ("api-key".to_string(), "secret".to_string()));
+        assert!(headers.contains(&("Accept".to_string(), "text/event-stream".to_string())));
+
+        let headers = request_headers(
+            "https://api.openai.com/v1",
+            "sk-test",
+            false,
+            &RequestOptions::default(),
+        );
+        assert_eq!(
+            headers[0],
+            ("Authorization".to_string(), "Bearer sk-test".to_string())
+        );
+        assert!(!headers.iter().any(|(k, _)| k == "Accept"));
+
+        // Forcing Azure mode for a gateway
+        let headers = request_headers(
+            "https://gateway.example.com",
+            "k",
+            false,
+            &options(r#"{"azure":true}"#),
+        );
+        assert_eq!(headers[0].0, "api-key");
+    }
+
+    #[test]
+    fn test_organization_project_and_openrouter_headers() {
+        let opts = options(
+            r#"{"organization":"org-1","project":"proj-1","app_url":"https://example.com","app_name":"Trustee"}"#,
+        );
+
+        let headers = request_headers("https://openrouter.ai/api/v1", "sk-or", false, &opts);
+        let get = |name: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(get("OpenAI-Organization"), Some("org-1"));
+        assert_eq!(get("OpenAI-Project"), Some("proj-1"));
+        assert_eq!(get("HTTP-Referer"), Some("https://example.com"));
+        assert_eq!(get("X-Title"), Some("Trustee"));
+
+        // OpenRouter attribution is not sent to other servers
+        let headers = request_headers("https://api.openai.com/v1", "sk", false, &opts);
+        assert!(!headers.iter().any(|(k, _)| k == "X-Title"));
+    }
+
+    #[test]
+    fn test_extra_headers_and_copilot_headers() {
+        let opts = options(
+            r#"{"extra_headers":{"content-type":"application/json; charset=utf-8","X-Trace":"1"}}"#,
+        );
+        let headers = request_headers("https://api.openai.com/v1", "sk", false, &opts);
+        assert!(headers.contains(&(
+            "content-type".to_string(),
+            "application/json; charset=utf-8".to_string()
+        )));
+        assert!(!headers.iter().any(|(k, _)| k == "Content-Type"));
+        assert!(headers.contains(&("X-Trace".to_string(), "1".to_string())));
+
+        assert!(is_forbidden_header("X-Initiator"));
+        assert!(is_forbidden_header("copilot-integration-id"));
+        assert!(!is_forbidden_header("Authorization"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Error classification for OpenAI-style error responses
+//!
+//! Maps HTTP status, the error body (`{"error":{"message","type","code","param"}}`)
+//! and rate-limit headers onto a `ProviderError` with a stable `code`, a correct
+//! `is_retryable` and a `retry_after` hint.
+
+use serde_json::Value;
+
+use crate::exports::abk::extension::provider::ProviderError;
+
+/// Classify an error response.
+/// `status` is `None` when the error body arrived without an HTTP error status
+/// (e.g. inside a 200 response or an SSE event).
+pub(crate) fn classify(
+    status: Option<u16>,
+    headers: &[(String, String)],
+    body: &str,
+) -> ProviderError {
+    let json: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+    let error = error_object(&json);
+
+    let message = error
+        .and_then(|e| e["message"].as_str().or_else(|| e.as_str()))
+        .map(str::to_string)
+        .unwrap_or_else(|| match status {
+            Some(status) if body.trim().is_empty() => format!("HTTP {}", status),
+            Some(status) => format!("HTTP {}: {}", status, body.trim()),
+            None => body.trim().to_string(),
+        });
+    let error_type = error.and_then(|e| e["type"].as_str()).unwrap_or("");
+    // Azure sends the status as a string code ("429"), others use a symbolic one
+    let error_code = error
+        .and_then(|e| match &e["code"] {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let param = error.and_then(|e| e["param"].as_str());
+
+    let (code, is_retryable) = error_code_for(status, error_type, &error_code, &message);
+
+    let retry_after = if is_retryable {
+        retry_after_from_headers(headers).or_else(|| retry_after_from_message(&message))
+    } else {
+        None
+    };
+
+    let message = match param {
+        Some(param) => format!("{} (param: {})", message, param),
+        None => message,
+    };
+
+    ProviderError {
+        message,
+        code: Some(code.to_string()),
+        http_status: status,
+        response_body: Some(body.to_string()),
+        is_retryable: Some(is_retryable),
+        retry_after,
+    }
+}
+
+/// Whether a successful-status body is actually an error payload
+pub(crate) fn is_error_body(json: &Value) -> bool {
+    json.get("choices").is_none() && error_object(json).is_some()
+}
+
+/// Locate the error object: `{"error":{...}}`, `{"error":"..."}` or
+/// Gemini-style `[{"error":{...}}]`
+fn error_object(json: &Value) -> Option<&Value> {
+    let root = match json {
+        Value::Array(items) => items.first()?,
+        other => other,
+    };
+    root.get("error").filter(|e| !e.is_null())
+}
+
+/// Map status and error identifiers to a stable code and retryability
+fn error_code_for(
+    status: Option<u16>,
+    error_type: &str,
+    error_code: &str,
+    message: &str,
+) -> (&'static str, bool) {
+    let ids = [error_code, error_type];
+    let has = |id: &str| ids.contains(&id);
+    let message = message.to_lowercase();
+
+    // Identifier-based classification wins over the status code: a 429 with
+    // insufficient_quota must not be retried, a 400 with context_length_exceeded
+    // needs compaction rather than a generic invalid-request error
+    if has("context_length_exceeded")
+        || message.contains("maximum context length")
+        || message.contains("context window")
+    {
+        return ("CONTEXT_LENGTH_EXCEEDED", false);
+    }
+    if has("insufficient_quota") || has("billing_hard_limit_reached") {
+        return ("INSUFFICIENT_QUOTA", false);
+    }
+    if has("rate_limit_exceeded") || has("rate_limit_error") || has("tokens") || has("requests") {
+        return ("RATE_LIMITED", true);
+    }
+    if has("invalid_api_key") || has("authentication_error") || has("invalid_authentication") {
+        return ("AUTHENTICATION_ERROR", false);
+    }
+    if has("model_not_found") {
+        return ("MODEL_NOT_FOUND", false);
+    }
+    if has("content_filter") || has("content_policy_violation") {
+        return ("CONTENT_FILTERED", false);
+    }
+    if has("server_error") || has("api_error") || has("overloaded_error") {
+        return ("SERVER_ERROR", true);
+    }
+
+    match status {
+        Some(401) => ("AUTHENTICATION_ERROR", false),
+        Some(403) => ("PERMISSION_DENIED", false),
+        Some(404) => ("NOT_FOUND", false),
+        Some(408) => ("TIMEOUT", true),
+        Some(409) => ("CONFLICT", true),
+        Some(413) => ("REQUEST_TOO_LARGE", false),
+        Some(429) => ("RATE_LIMITED", true),
+        Some(400) | Some(422) => ("INVALID_REQUEST", false),
+        Some(s) if s >= 500 => ("SERVER_ERROR", true),
+        _ if has("invalid_request_error") => ("INVALID_REQUEST", false),
+        _ => ("API_ERROR", false),
+    }
+}
+
+/// Seconds to wait, from `retry-after-ms`, `retry-after` or the
+/// `x-ratelimit-reset-*` headers (the longest reset wins)
+fn retry_after_from_headers(headers: &[(String, String)]) -> Option<u32> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim())
+    };
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(ceil_seconds(ms / 1000.0));
+    }
+    if let Some(secs) = header("retry-after").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(ceil_seconds(secs));
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| header(name).and_then(parse_duration))
+        .reduce(f64::max)
+        .map(ceil_seconds)
+}
+
+/// OpenAI rate-limit messages end with "Please try again in 1.2s."
+fn retry_after_from_message(message: &str) -> Option<u32> {
+    let rest = &message[message.find("try again in ")? + "try again in ".len()..];
+    let token = rest.split_whitespace().next()?.trim_end_matches(['.', ',']);
+    parse_duration(token).map(ceil_seconds)
+}
+
+/// Parse Go-style durations used by OpenAI reset headers ("1s", "6m0s", "20ms",
+/// "1h2m3.5s"); bare numbers are seconds
+fn parse_duration(value: &str) -> Option<f64> {
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(secs);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    let mut parsed_any = false;
+
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        let unit_seconds = match c {
+            'h' => 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                0.001
+            }
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return None,
+        };
+        total += amount * unit_seconds;
+        parsed_any = true;
+    }
+
+    (parsed_any && number.is_empty()).then_some(total)
+}
+
+fn ceil_seconds(secs: f64) -> u32 {
+    secs.max(0.0).ceil() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exports::abk::extension::provider::Guest as ProviderGuest;
+    use crate::OpenAIProvider;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rate_limit_uses_reset_headers() {
+        let body = r#"{"error":{"message":"Rate limit reached for requests","type":"requests","code":"rate_limit_exceeded","param":null}}"#;
+        let err = OpenAIProvider::parse_error(
+            429,
+            headers(&[
+                ("x-ratelimit-reset-requests", "1.5s"),
+                ("X-RateLimit-Reset-Tokens", "6m0s"),
+            ]),
+            body.to_string(),
+        );
+        assert_eq!(err.code, Some("RATE_LIMITED".to_string()));
+        assert_eq!(err.is_retryable, Some(true));
+        assert_eq!(err.retry_after, Some(360));
+        assert_eq!(err.http_status, Some(429));
+    }
+
+    #[test]
+    fn test_retry_after_header_wins() {
+        let err = OpenAIProvider::parse_error(
+            503,
+            headers(&[("Retry-After", "7"), ("x-ratelimit-reset-requests", "1m")]),
+            String::new(),
+        );
+        assert_eq!(err.code, Some("SERVER_ERROR".to_string()));
+        assert_eq!(err.retry_after, Some(7));
+        assert_eq!(err.message, "HTTP 503");
+    }
+
+    #[test]
+    fn test_insufficient_quota_is_not_retryable() {
+        let body = r#"{"error":{"message":"You exceeded your current quota","type":"insufficient_quota","code":"insufficient_quota"}}"#;
+        let err =
+            OpenAIProvider::parse_error(429, headers(&[("retry-after", "20")]), body.to_string());
+        assert_eq!(err.code, Some("INSUFFICIENT_QUOTA".to_string()));
+        assert_eq!(err.is_retryable, Some(false));
+        assert_eq!(err.retry_after, None);
+    }
+
+    #[test]
+    fn test_context_length_and_auth() {
+        let body = r#"{"error":{"message":"This model's maximum context length is 8192 tokens","type":"invalid_request_error","code":"context_length_exceeded","param":"messages"}}"#;
+        let err = OpenAIProvider::parse_error(400, Vec::new(), body.to_string());
+        assert_eq!(err.code, Some("CONTEXT_LENGTH_EXCEEDED".to_string()));
+        assert!(err.message.ends_with("(param: messages)"));
+
+        let body = r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+        let err = OpenAIProvider::parse_error(401, Vec::new(), body.to_string());
+        assert_eq!(err.code, Some("AUTHENTICATION_ERROR".to_string()));
+        assert_eq!(err.is_retryable, Some(false));
+    }
+
+    #[test]
+    fn test_retry_after_from_message() {
+        let body = r#"{"error":{"message":"Rate limit reached. Please try again in 820ms.","code":"rate_limit_exceeded"}}"#;
+        let err = OpenAIProvider::parse_error(429, Vec::new(), body.to_string());
+        assert_eq!(err.retry_after, Some(1));
+    }
+
+    #[test]
+    fn test_parse_response_reports_error_body() {
+        let body =
+            r#"{"error":{"message":"The server had an error","type":"server_error","code":null}}"#;
+        let err =
+            OpenAIProvider::parse_response(body.to_string(), "gpt-4o".to_string()).unwrap_err();
+        assert_eq!(err.code, Some("SERVER_ERROR".to_string()));
+        assert_eq!(err.is_retryable, Some(true));
+        assert_eq!(err.message, "The server had an error");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("20ms"), Some(0.02));
+        assert_eq!(parse_duration("1h2m3s"), Some(3723.0));
+        assert_eq!(parse_duration("soon"), None);
+    }
+}
@@ -0,0 +1,411 @@
+//! Fitting history into the context window
+//!
+//! When the estimated prompt is over budget, whole turns are dropped oldest
+//! first. A turn starts at a user message and runs up to the next one, so a
+//! tool call is never separated from its result. After that, an agent loop's
+//! latest turn can still hold a long run of calls, so its complete tool
+//! call/result pairs go next, oldest first. System messages, the last user
+//! message, the newest pair and anything after it are always kept. In
+//! `summarize` mode the dropped messages are replaced by a short extractive
+//! summary, as long as it still fits.
+//!
+//! The token count is an estimate, so part of the budget (`fit_margin`,
+//! 10% by default) is kept free to absorb its error.
+
+use serde_json::{json, Value};
+
+use crate::exports::abk::extension::provider::ProviderError;
+use crate::messages::HistoryStyle;
+use crate::options::RequestOptions;
+use crate::{models, tokens};
+
+/// Longest excerpt of one message in a summary, in characters
+const EXCERPT_CHARS: usize = 100;
+/// Share of the budget left free when `fit_margin` is not set
+const DEFAULT_MARGIN: f64 = 0.1;
+
+/// What fitting changed
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FitReport {
+    /// Indices of the dropped InternalMessages, ascending
+    pub dropped: Vec<u32>,
+    /// Whether a summary of the dropped messages was inserted
+    pub summarized: bool,
+    /// Estimated prompt tokens after fitting, including `overhead`
+    pub prompt_tokens: u32,
+}
+
+/// Trim InternalMessages for a request to `model` so the prompt fits the
+/// context window minus the reserved output (`max_tokens`, else the model's
+/// output limit) and the safety margin
+pub(crate) fn fit_request(
+    messages: Vec<Value>,
+    model: &str,
+    tools_json: Option<&str>,
+    max_tokens: Option<u32>,
+    options: &RequestOptions,
+    summarize: bool,
+) -> Result<(Vec<Value>, FitReport), ProviderError> {
+    let tools: Vec<Value> = tools_json
+        .and_then(|t| serde_json::from_str(t).ok())
+        .unwrap_or_default();
+    let overhead = tokens::tools_tokens(&tools) + tokens::REPLY_PRIMING;
+
+    let capabilities = models::capabilities(model).1;
+    let reserve = max_tokens.unwrap_or(capabilities.max_output_tokens);
+    let budget = capabilities.context_window.saturating_sub(reserve);
+    let margin = options.fit_margin.unwrap_or(DEFAULT_MARGIN);
+    let limit = (f64::from(budget) * (1.0 - margin)) as u32;
+
+    fit(
+        messages,
+        &options.history_style(model),
+        overhead,
+        limit,
+        summarize,
+    )
+}
+
+/// Trim InternalMessages so that they plus `overhead` (tools, reply priming)
+/// fit in `limit` tokens
+fn fit(
+    messages: Vec<Value>,
+    style: &HistoryStyle,
+    overhead: u32,
+    limit: u32,
+    summarize: bool,
+) -> Result<(Vec<Value>, FitReport), ProviderError> {
+    let sizes = tokens::per_message(&messages, style);
+    let mut used = overhead + sizes.iter().sum::<u32>();
+
+    let mut dropped: Vec<usize> = Vec::new();
+    let mut summary = None;
+    let mut turns = droppable_turns(&messages).into_iter();
+    while used + summary_tokens(&summary, style) > limit {
+        let Some(turn) = turns.next() else {
+            break;
+        };
+        used -= turn.iter().map(|&i| sizes[i]).sum::<u32>();
+        dropped.extend(turn);
+        if summarize {
+            summary = Some(summary_message(&messages, &dropped));
+        }
+    }
+
+    // A summary that does not fit is left out
+    if used + summary_tokens(&summary, style) > limit {
+        summary = None;
+    }
+    if used > limit {
+        return Err(ProviderError {
+            message: format!(
+                "Request needs about {} tokens after dropping all earlier turns; the limit is {}",
+                used, limit
+            ),
+            code: Some("CONTEXT_LENGTH_EXCEEDED".to_string()),
+            http_status: None,
+            response_body: None,
+            is_retryable: Some(false),
+            retry_after: None,
+        });
+    }
+
+    let report = FitReport {
+        dropped: dropped.iter().map(|&i| i as u32).collect(),
+        summarized: summary.is_some(),
+        prompt_tokens: used + summary_tokens(&summary, style),
+    };
+
+    let mut kept: Vec<Value> = messages
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !dropped.contains(i))
+        .map(|(_, msg)| msg)
+        .collect();
+    if let Some(summary) = summary {
+        // After the leading system prompt
+        let at = kept
+            .iter()
+            .position(|m| !is_system(m))
+            .unwrap_or(kept.len());
+        kept.insert(at, summary);
+    }
+    Ok((kept, report))
+}
+
+/// Groups of messages that may be dropped, oldest first: everything before
+/// the latest turn except system messages, then the older tool call/result
+/// pairs inside it
+fn droppable_turns(messages: &[Value]) -> Vec<Vec<usize>> {
+    let latest = messages
+        .iter()
+        .rposition(starts_turn)
+        .unwrap_or(messages.len().saturating_sub(1));
+
+    let mut turns: Vec<Vec<usize>> = Vec::new();
+    for (i, msg) in messages[..latest].iter().enumerate() {
+        if is_system(msg) {
+            continue;
+        }
+        match turns.last_mut() {
+            Some(turn) if !starts_turn(msg) => turn.push(i),
+            _ => turns.push(vec![i]),
+        }
+    }
+    turns.extend(older_tool_pairs(messages, latest + 1));
+    turns
+}
+
+/// Complete tool call/result pairs from `start` on, oldest first, without
+/// the newest one: an assistant message with tool calls plus the result
+/// messages right after it that answer every call
+fn older_tool_pairs(messages: &[Value], start: usize) -> Vec<Vec<usize>> {
+    let mut pairs = Vec::new();
+    let mut i = start;
+    while i < messages.len() {
+        let calls = block_ids(&messages[i], "tool_use", "id");
+        if messages[i]["role"] != "assistant" || calls.is_empty() {
+            i += 1;
+            continue;
+        }
+
+        let mut pair = vec![i];
+        let mut answered = Vec::new();
+        i += 1;
+        while i < messages.len() && is_tool_result(&messages[i]) {
+            answered.extend(block_ids(&messages[i], "tool_result", "tool_use_id"));
+            pair.push(i);
+            i += 1;
+        }
+        if calls.iter().all(|id| answered.contains(id)) {
+            pairs.push(pair);
+        }
+    }
+    pairs.pop();
+    pairs
+}
+
+/// `key` of every content block of type `block_type`
+fn block_ids<'a>(msg: &'a Value, block_type: &str, key: &str) -> Vec<&'a str> {
+    msg["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|b| b["type"] == block_type)
+        .filter_map(|b| b[key].as_str())
+        .collect()
+}
+
+fn is_tool_result(msg: &Value) -> bool {
+    matches!(msg["role"].as_str(), Some("tool" | "user")) && !starts_turn(msg)
+}
+
+/// A user message other than one carrying only tool results
+fn starts_turn(msg: &Value) -> bool {
+    msg["role"] == "user"
+        && !msg["content"]
+            .as_array()
+            .is_some_and(|blocks| blocks.iter().all(|b| b["type"] == "tool_result"))
+}
+
+fn is_system(msg: &Value) -> bool {
+    matches!(msg["role"].as_str(), Some("system" | "developer"))
+}
+
+fn summary_tokens(summary: &Option<Value>, style: &HistoryStyle) -> u32 {
+    summary
+        .as_ref()
+        .map(|s| tokens::per_message(std::slice::from_ref(s), style)[0])
+        .unwrap_or(0)
+}
+
+fn summary_message(messages: &[Value], dropped: &[usize]) -> Value {
+    let mut text = format!(
+        "Summary of {} earlier messages removed to fit the context window:",
+        dropped.len()
+    );
+    for &i in dropped {
+        let msg = &messages[i];
+        text.push_str(&format!(
+            "\n- {}: {}",
+            msg["role"].as_str().unwrap_or("user"),
+            excerpt(&msg["content"])
+        ));
+    }
+    json!({ "role": "system", "content": text })
+}
+
+/// One-line gist of message content
+fn excerpt(content: &Value) -> String {
+    let parts: Vec<String> = match content {
+        Value::String(text) => vec![text.clone()],
+        Value::Array(blocks) => blocks
+            .iter()
+            .map(|block| match block["type"].as_str() {
+                Some("tool_use") => format!("called {}", block["name"].as_str().unwrap_or("tool")),
+                Some("tool_result") => "tool result".to_string(),
+                Some("image") | Some("image_url") => "[image]".to_string(),
+                _ => block["text"].as_str().unwrap_or("").to_string(),
+            })
+            .filter(|part| !part.is_empty())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let line = parts
+        .join("; ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if line.chars().count() > EXCERPT_CHARS {
+        let cut: String = line.chars().take(EXCERPT_CHARS).collect();
+        format!("{}...", cut.trim_end())
+    } else {
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<Value> {
+        vec![
+            json!({ "role": "system", "content": "You are a coding agent." }),
+            json!({ "role": "user", "content": "Read main.rs ".repeat(50) }),
+            json!({ "role": "assistant", "content": [
+                { "type": "tool_use", "id": "call_1", "name": "read_file", "input": { "path": "main.rs" } }
+            ]}),
+            json!({ "role": "tool", "content": [
+                { "type": "tool_result", "tool_use_id": "call_1", "content": "fn main() {}".repeat(100) }
+            ]}),
+            json!({ "role": "assistant", "content": "It is empty." }),
+            json!({ "role": "user", "content": "Add a test." }),
+        ]
+    }
+
+    fn total(messages: &[Value]) -> u32 {
+        tokens::per_message(messages, &HistoryStyle::default())
+            .iter()
+            .sum()
+    }
+
+    #[test]
+    fn test_fits_unchanged() {
+        let messages = history();
+        let limit = total(&messages) + 10;
+        let (fitted, report) =
+            fit(messages.clone(), &HistoryStyle::default(), 10, limit, false).unwrap();
+        assert_eq!(fitted, messages);
+        assert!(report.dropped.is_empty());
+        assert_eq!(report.prompt_tokens, limit);
+    }
+
+    #[test]
+    fn test_drops_oldest_turn_with_tool_pairs() {
+        let messages = history();
+        let kept = [&messages[0], &messages[5]];
+        let limit = kept
+            .iter()
+            .map(|m| total(std::slice::from_ref(m)))
+            .sum::<u32>()
+            + 5;
+
+        let (fitted, report) =
+            fit(messages.clone(), &HistoryStyle::default(), 0, limit, false).unwrap();
+        assert_eq!(report.dropped, vec![1, 2, 3, 4]);
+        assert!(!report.summarized);
+        assert_eq!(fitted, vec![messages[0].clone(), messages[5].clone()]);
+
+        // The latest turn alone is over the limit
+        let err = fit(messages, &HistoryStyle::default(), 0, 5, false).unwrap_err();
+        assert_eq!(err.code, Some("CONTEXT_LENGTH_EXCEEDED".to_string()));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let messages = history();
+        let limit = total(&messages[..1]) + total(&messages[5..]) + 200;
+
+        let (fitted, report) =
+            fit(messages.clone(), &HistoryStyle::default(), 0, limit, true).unwrap();
+        assert!(report.summarized);
+        assert_eq!(fitted.len(), 3);
+        assert_eq!(fitted[1]["role"], "system");
+        let summary = fitted[1]["content"].as_str().unwrap();
+        assert!(summary.starts_with("Summary of 4 earlier messages"));
+        assert!(summary.contains("- assistant: called read_file"));
+        assert!(report.prompt_tokens <= limit);
+    }
+
+    #[test]
+    fn test_drops_older_pairs_in_latest_turn() {
+        let call = |id: &str| {
+            json!({ "role": "assistant", "content": [
+                { "type": "tool_use", "id": id, "name": "read_file", "input": { "path": id } }
+            ]})
+        };
+        let result = |id: &str| {
+            json!({ "role": "tool", "content": [
+                { "type": "tool_result", "tool_use_id": id, "content": "x ".repeat(200) }
+            ]})
+        };
+        // One user message, then an agent loop of calls
+        let messages = vec![
+            json!({ "role": "system", "content": "You are a coding agent." }),
+            json!({ "role": "user", "content": "Fix the build." }),
+            call("a"),
+            result("a"),
+            call("b"),
+            result("b"),
+            call("c"),
+            result("c"),
+        ];
+        let kept = [0, 1, 6, 7];
+        let limit = kept
+            .iter()
+            .map(|&i| total(std::slice::from_ref(&messages[i])))
+            .sum::<u32>()
+            + 5;
+
+        let (fitted, report) =
+            fit(messages.clone(), &HistoryStyle::default(), 0, limit, false).unwrap();
+        assert_eq!(report.dropped, vec![2, 3, 4, 5]);
+        let expected: Vec<Value> = kept.iter().map(|&i| messages[i].clone()).collect();
+        assert_eq!(fitted, expected);
+
+        // The newest pair is never dropped
+        assert_eq!(droppable_turns(&messages), vec![vec![2, 3], vec![4, 5]]);
+    }
+
+    #[test]
+    fn test_margin_at_the_limit() {
+        let messages = history();
+        let estimate = total(&messages) + tokens::REPLY_PRIMING;
+        // Reserve exactly what leaves the estimate at the limit
+        let window = models::capabilities("gpt-4o").1.context_window;
+        let max_tokens = Some(window - estimate);
+
+        let fit_with = |options: &str| {
+            let options = RequestOptions::parse(Some(options)).unwrap();
+            fit_request(
+                messages.clone(),
+                "gpt-4o",
+                None,
+                max_tokens,
+                &options,
+                false,
+            )
+            .unwrap()
+        };
+
+        // The default margin makes room for the estimate's error
+        let (_, report) = fit_with("{}");
+        assert_eq!(report.dropped, vec![1, 2, 3, 4]);
+
+        let (fitted, report) = fit_with(r#"{"fit_margin":0}"#);
+        assert!(report.dropped.is_empty());
+        assert_eq!(fitted, messages);
+        assert_eq!(report.prompt_tokens, estimate);
+    }
+}
@@ -0,0 +1,240 @@
+//! Legacy `functions` / `function_call` protocol
+//!
+//! Older Azure deployments and some self-hosted servers predate `tools`: the
+//! request lists `functions`, the model replies with one
+//! `message.function_call` (no id) and results come back as `function`
+//! messages matched by name. Requests are rewritten from the `tools` form,
+//! and replies get a synthesized tool-call id. The id hashes the completion
+//! id with the call's position, name and arguments, so streaming and parsing
+//! agree. Without a completion id, the same call in two turns gets the same
+//! id; results are matched by name in this protocol, so nothing is lost.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::{ids, Choice, OpenAIToolCall};
+
+/// Rewrite a Chat Completions body from `tools` to the legacy fields
+pub(crate) fn apply(body: &mut Value) {
+    let Some(obj) = body.as_object_mut() else {
+        return;
+    };
+    let tools = obj.remove("tools");
+    let tool_choice = obj.remove("tool_choice");
+    obj.remove("parallel_tool_calls");
+
+    let functions: Vec<Value> = tools
+        .as_ref()
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|tool| tool["function"].clone())
+        .collect();
+
+    if let Some(choice) = tool_choice {
+        let function_call = match choice.as_str() {
+            Some("required") if functions.len() == 1 => json!({ "name": functions[0]["name"] }),
+            // No way to force "any function" in the legacy protocol
+            Some("required") => json!("auto"),
+            Some(mode) => json!(mode),
+            None => json!({ "name": choice["function"]["name"] }),
+        };
+        body["function_call"] = function_call;
+    }
+    if !functions.is_empty() {
+        body["functions"] = json!(functions);
+    }
+
+    if let Some(messages) = body["messages"].as_array_mut() {
+        *messages = rewrite_history(std::mem::take(messages));
+    }
+}
+
+/// Assistant tool calls become one `function_call` message per call, each
+/// followed by its result as a `function` message
+fn rewrite_history(messages: Vec<Value>) -> Vec<Value> {
+    let names: HashMap<String, String> = messages
+        .iter()
+        .flat_map(|m| m["tool_calls"].as_array().into_iter().flatten())
+        .filter_map(|call| {
+            Some((
+                call["id"].as_str()?.to_string(),
+                call["function"]["name"].as_str()?.to_string(),
+            ))
+        })
+        .collect();
+    let function_message = |result: &Value| {
+        let id = result["tool_call_id"].as_str().unwrap_or("");
+        json!({
+            "role": "function",
+            "name": names.get(id).map(String::as_str).unwrap_or("function"),
+            "content": result["content"]
+        })
+    };
+
+    let mut out = Vec::new();
+    let mut messages = messages.into_iter().peekable();
+    while let Some(mut msg) = messages.next() {
+        let calls = match msg.as_object_mut().and_then(|m| m.remove("tool_calls")) {
+            Some(Value::Array(calls)) if !calls.is_empty() => calls,
+            _ if msg["role"] == "tool" => {
+                out.push(function_message(&msg));
+                continue;
+            }
+            _ => {
+                out.push(msg);
+                continue;
+            }
+        };
+
+        let mut results = Vec::new();
+        while let Some(result) = messages.next_if(|m| m["role"] == "tool") {
+            results.push(result);
+        }
+
+        for (i, call) in calls.iter().enumerate() {
+            let mut call_msg = if i == 0 {
+                msg.clone()
+            } else {
+                json!({ "role": "assistant", "content": null })
+            };
+            call_msg["function_call"] = json!({
+                "name": call["function"]["name"],
+                "arguments": call["function"]["arguments"]
+            });
+            out.push(call_msg);
+
+            if let Some(pos) = results.iter().position(|r| r["tool_call_id"] == call["id"]) {
+                out.push(function_message(&results.remove(pos)));
+            }
+        }
+        out.extend(results.iter().map(function_message));
+    }
+    out
+}
+
+/// Turn a legacy `message.function_call` into a tool call, and give every
+/// tool call sent without an id one
+pub(crate) fn extract_into(choice: &mut Choice, completion_id: Option<&str>) {
+    let message = &mut choice.message;
+    if let Some(call) = message.function_call.take() {
+        if message.tool_calls.as_ref().is_none_or(|c| c.is_empty()) {
+            message.tool_calls = Some(vec![OpenAIToolCall {
+                id: String::new(),
+                call_type: "function".to_string(),
+                function: call,
+            }]);
+            if matches!(
+                choice.finish_reason.as_deref(),
+                Some("function_call" | "stop")
+            ) {
+                choice.finish_reason = Some("tool_calls".to_string());
+            }
+        }
+    }
+
+    let calls = message.tool_calls.iter_mut().flatten();
+    for (position, call) in calls.enumerate().filter(|(_, c)| c.id.is_empty()) {
+        call.id = call_id(
+            completion_id,
+            position,
+            &call.function.name,
+            &call.function.arguments,
+        );
+    }
+}
+
+/// Stable id for a call that came without one. Only known once the
+/// arguments are complete, so streams report it at the finish.
+pub(crate) fn call_id(
+    completion_id: Option<&str>,
+    position: usize,
+    name: &str,
+    arguments: &str,
+) -> String {
+    ids::call_id(&[
+        completion_id.unwrap_or(""),
+        &position.to_string(),
+        name,
+        arguments,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut body = json!({
+            "model": "gpt-35-turbo",
+            "messages": [
+                { "role": "user", "content": "Weather and time in Paris?" },
+                { "role": "assistant", "content": "Checking.", "tool_calls": [
+                    { "id": "call_a", "type": "function",
+                      "function": { "name": "get_weather", "arguments": "{}" } },
+                    { "id": "call_b", "type": "function",
+                      "function": { "name": "get_time", "arguments": "{}" } }
+                ]},
+                { "role": "tool", "tool_call_id": "call_b", "content": "noon" },
+                { "role": "tool", "tool_call_id": "call_a", "content": "sunny" }
+            ],
+            "tools": [
+                { "type": "function", "function": { "name": "get_weather", "parameters": {} } }
+            ],
+            "tool_choice": "required",
+            "parallel_tool_calls": false
+        });
+        apply(&mut body);
+
+        assert!(body.get("tools").is_none());
+        assert!(body.get("parallel_tool_calls").is_none());
+        assert_eq!(body["functions"][0]["name"], "get_weather");
+        assert_eq!(body["function_call"], json!({ "name": "get_weather" }));
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[1]["content"], "Checking.");
+        assert_eq!(messages[1]["function_call"]["name"], "get_weather");
+        assert!(messages[1].get("tool_calls").is_none());
+        assert_eq!(
+            messages[2],
+            json!({ "role": "function", "name": "get_weather", "content": "sunny" })
+        );
+        assert_eq!(messages[3]["function_call"]["name"], "get_time");
+        assert_eq!(messages[4]["name"], "get_time");
+    }
+
+    #[test]
+    fn test_call_ids_differ_without_completion_id() {
+        let reply = |arguments: &str| Choice {
+            message: crate::ResponseMessage {
+                role: "assistant".to_string(),
+                function_call: Some(crate::FunctionCall {
+                    name: "read_file".to_string(),
+                    arguments: arguments.to_string(),
+                }),
+                ..crate::ResponseMessage::default()
+            },
+            finish_reason: Some("function_call".to_string()),
+        };
+        let id = |arguments: &str| {
+            let mut choice = reply(arguments);
+            extract_into(&mut choice, None);
+            choice.message.tool_calls.unwrap()[0].id.clone()
+        };
+
+        // Without a completion id, only the call itself tells turns apart
+        let first = id(r#"{"path":"a.rs"}"#);
+        assert!(first.starts_with("call_"));
+        assert_ne!(first, id(r#"{"path":"b.rs"}"#));
+        assert_eq!(first, id(r#"{"path":"a.rs"}"#));
+
+        // Parallel calls to one function differ by position
+        assert_ne!(
+            call_id(None, 0, "read_file", "{}"),
+            call_id(None, 1, "read_file", "{}")
+        );
+    }
+}
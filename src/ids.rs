@@ -0,0 +1,41 @@
+//! Deterministic ids
+//!
+//! Ids the provider makes up (for tool calls a model sent without one, and
+//! for backends with strict id formats) are hashes of what identifies the
+//! item, so a stream and a parsed body, or a call and its result, agree
+//! without keeping state.
+
+/// FNV-1a hash of `parts`, joined with NUL separators
+pub(crate) fn hash(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            hash = step(hash, 0);
+        }
+        hash = part.bytes().fold(hash, step);
+    }
+    hash
+}
+
+fn step(hash: u64, byte: u8) -> u64 {
+    (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+}
+
+/// `call_` id hashed from `parts`
+pub(crate) fn call_id(parts: &[&str]) -> String {
+    format!("call_{:016x}", hash(parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        // Reference FNV-1a values
+        assert_eq!(hash(&[""]), 0xcbf29ce484222325);
+        assert_eq!(hash(&["a"]), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(&["a", "b"]), hash(&["a\0b"]));
+        assert_ne!(hash(&["ab", ""]), hash(&["a", "b"]));
+    }
+}
@@ -3,27 +3,52 @@
 //! This extension provides OpenAI-compatible API communication for ABK agents.
 //! It supports:
 //! - Standard OpenAI chat completions API
+//! - OpenAI Responses API (`/v1/responses`) for models that require it
 //! - Function/tool calling
 //! - Streaming responses
 //!
-//! Headers: Only standard headers (Authorization, Content-Type)
-//! NO GitHub Copilot-specific headers (X-Request-Id, X-Initiator, etc.)
+//! Headers: Only standard headers (Authorization, Content-Type), built by
+//! `get-request-headers`. GitHub Copilot-specific headers (X-Request-Id,
+//! X-Initiator, etc.) are never sent — see `endpoint.rs`.
 
 wit_bindgen::generate!({
     world: "provider-extension",
     path: "wit",
+    additional_derives: [PartialEq],
 });
 
 use exports::abk::extension::core::{ExtensionMetadata, Guest as CoreGuest};
 use exports::abk::extension::provider::{
     AssistantMessage as WitAssistantMessage, Config as WitConfig, ContentDelta as WitContentDelta,
-    Guest as ProviderGuest, Message as WitMessage, ProviderError, Tool as WitTool,
-    ToolCall as WitToolCall,
+    EmbeddingsResponse as WitEmbeddingsResponse, FitResult as WitFitResult, Guest as ProviderGuest,
+    ListedModel as WitListedModel, Message as WitMessage, ModelInfo as WitModelInfo, ProviderError,
+    TokenCount as WitTokenCount, Tool as WitTool, ToolCall as WitToolCall, Usage as WitUsage,
 };
 
-use serde::{Deserialize, Serialize};
+use options::RequestOptions;
+use serde::Deserialize;
 use serde_json::{json, Value};
 
+mod backends;
+mod conversation;
+mod embeddings;
+mod endpoint;
+mod errors;
+mod fit;
+mod functions;
+mod ids;
+mod messages;
+mod model_list;
+mod models;
+mod options;
+mod prompt_tools;
+mod responses;
+mod schema;
+mod stream;
+mod tags;
+mod think;
+mod tokens;
+
 /// The OpenAI provider extension implementation
 struct OpenAIProvider;
 
@@ -38,7 +63,7 @@ impl CoreGuest for OpenAIProvider {
             id: "openai-unofficial".to_string(),
             name: "OpenAI Unofficial Provider".to_string(),
             version: "0.1.0".to_string(),
-            api_version: "0.3.0".to_string(),
+            api_version: "0.4.0".to_string(),
             description: "OpenAI-compatible API provider with streaming and function calling"
                 .to_string(),
         }
@@ -63,11 +88,12 @@ impl ProviderGuest for OpenAIProvider {
             "version": "0.1.0",
             "description": "OpenAI-compatible API provider - works with any OpenAI-compatible endpoint",
             "supported_models": "any",
+            // Protocol features; what each model accepts (vision, tools, ...) is in "models"
             "features": {
                 "streaming": true,
-                "function_calling": true,
-                "vision": false
+                "function_calling": true
             },
+            "models": models::catalog(),
             "default_model": "gpt-4o-mini"
         });
         serde_json::to_string(&metadata).unwrap_or_default()
@@ -132,203 +158,106 @@ impl ProviderGuest for OpenAIProvider {
     }
 
     /// Parse response from OpenAI API
-    fn parse_response(body: String, _model: String) -> Result<WitAssistantMessage, ProviderError> {
-        let response: OpenAIResponse = serde_json::from_str(&body).map_err(|e| ProviderError {
-            message: format!("Failed to parse response: {}", e),
-            code: Some("PARSE_ERROR".to_string()),
-            http_status: None,
-            response_body: Some(body.clone()),
-            is_retryable: Some(false),
-            retry_after: None,
-        })?;
-
-        if response.choices.is_empty() {
-            return Err(ProviderError {
-                message: "No choices in response".to_string(),
-                code: Some("EMPTY_RESPONSE".to_string()),
-                http_status: None,
-                response_body: Some(body),
-                is_retryable: Some(false),
-                retry_after: None,
-            });
-        }
-
-        let message = &response.choices[0].message;
-
-        // Extract content
-        let content = message.content.clone();
-
-        // Extract reasoning content
-        let reasoning = message.reasoning_content.clone();
-
-        // Extract tool calls
-        let tool_calls: Vec<WitToolCall> = message
-            .tool_calls
-            .as_ref()
-            .map(|calls| {
-                calls
-                    .iter()
-                    .map(|call| WitToolCall {
-                        id: call.id.clone(),
-                        name: call.function.name.clone(),
-                        arguments: call.function.arguments.clone(),
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
+    fn parse_response(body: String, model: String) -> Result<WitAssistantMessage, ProviderError> {
+        let (id, mut choice, usage) = parse_body(body)?;
+        backends::select(&model, &RequestOptions::default()).shape_choice(&mut choice);
+        Ok(assistant_message(id, choice, usage))
+    }
 
-        Ok(WitAssistantMessage {
-            content,
-            tool_calls,
-            reasoning,
-        })
+    /// Classify an error response from the API
+    fn parse_error(status: u16, headers: Vec<(String, String)>, body: String) -> ProviderError {
+        errors::classify(Some(status), &headers, &body)
     }
 
     /// Handle streaming chunk (SSE format)
     fn handle_stream_chunk(chunk: String) -> Option<WitContentDelta> {
-        let chunk = chunk.trim();
+        stream::parse_chunk(&chunk)
+    }
 
-        // Check for SSE format
-        if !chunk.starts_with("data: ") && !chunk.contains("\ndata: ") {
-            return None;
-        }
+    /// Handle streaming chunk, returning every delta it carries
+    fn handle_stream_chunk_multi(chunk: String) -> Vec<WitContentDelta> {
+        stream::parse_chunk_multi(&chunk)
+    }
 
-        // Extract data part
-        let data = if let Some(stripped) = chunk.strip_prefix("data: ") {
-            stripped
-        } else if let Some(pos) = chunk.find("\ndata: ") {
-            &chunk[pos + 7..]
-        } else {
-            return None;
+    /// Begin a stateful stream
+    fn stream_begin(model: String) -> u32 {
+        // Same message as parse-response: no text-level extraction
+        let options = RequestOptions {
+            think_tags: Some(false),
+            ..RequestOptions::default()
         };
+        stream::begin(model, &options)
+    }
 
-        // Check for done marker
-        if data == "[DONE]" {
-            return Some(WitContentDelta {
-                delta_type: "done".to_string(),
-                content: None,
-                reasoning: None,
-                tool_call_index: None,
-                tool_call: None,
-                error: None,
-            });
-        }
-
-        // Parse JSON
-        let json: Value = serde_json::from_str(data).ok()?;
-
-        // Extract from choices array
-        let choices = json["choices"].as_array()?;
-        if choices.is_empty() {
-            return None;
-        }
+    /// Begin a stateful stream with request options
+    fn stream_begin_with_options(
+        model: String,
+        options_json: Option<String>,
+    ) -> Result<u32, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        Ok(stream::begin(model, &options))
+    }
 
-        let choice = &choices[0];
-        let delta = &choice["delta"];
+    /// Feed raw SSE data into a stream
+    fn stream_feed(handle: u32, data: String) -> Result<Vec<WitContentDelta>, ProviderError> {
+        stream::feed(handle, data)
+    }
 
-        // Check finish_reason — if it's an error (not "stop", "tool_calls", or null),
-        // return an error delta so the caller can retry instead of ending the session
-        if let Some(finish_reason) = choice["finish_reason"].as_str() {
-            match finish_reason {
-                "stop" | "tool_calls" | "length" | "content_filter" | "function_call" => {
-                    // Normal finish reasons — let processing continue below
-                }
-                _ => {
-                    // Abnormal finish reason (e.g., "network_error") — return error
-                    return Some(WitContentDelta {
-                        delta_type: "error".to_string(),
-                        content: None,
-                        reasoning: None,
-                        tool_call_index: None,
-                        tool_call: None,
-                        error: Some(format!("finish_reason: {}", finish_reason)),
-                    });
-                }
-            }
-        }
+    /// Finish a stream and return the accumulated assistant message
+    fn stream_finish(handle: u32) -> Result<WitAssistantMessage, ProviderError> {
+        stream::finish(handle)
+    }
 
-        // Debug: log the delta keys to see what fields are present
-        if std::env::var("RUST_LOG")
-            .map(|v| v.to_lowercase().contains("debug"))
-            .unwrap_or(false)
-        {
-            if let Some(obj) = delta.as_object() {
-                let keys: Vec<&str> = obj.keys().map(|s| s.as_str()).collect();
-                if !keys.is_empty() {
-                    eprintln!(
-                        "[WASM DEBUG] delta keys: {:?}, finish_reason: {:?}",
-                        keys,
-                        choice["finish_reason"].as_str()
-                    );
-                }
-            }
-        }
+    /// Release a stream without finishing it
+    fn stream_abort(handle: u32) {
+        stream::abort(handle)
+    }
 
-        // Check for reasoning_content delta (from thinking models like GLM, Qwen)
-        if let Some(reasoning) = delta["reasoning_content"].as_str() {
-            return Some(WitContentDelta {
-                delta_type: "reasoning".to_string(),
-                content: None,
-                reasoning: Some(reasoning.to_string()),
-                tool_call_index: None,
-                tool_call: None,
-                error: None,
-            });
-        }
+    /// Get API URL for OpenAI
+    fn get_api_url(base_url: String, model: String) -> String {
+        endpoint::api_url(&base_url, &model, &RequestOptions::default())
+    }
 
-        // Check for content delta
-        if let Some(content) = delta["content"].as_str() {
-            return Some(WitContentDelta {
-                delta_type: "content".to_string(),
-                content: Some(content.to_string()),
-                reasoning: None,
-                tool_call_index: None,
-                tool_call: None,
-                error: None,
-            });
-        }
+    /// Get API URL for a model with provider options
+    fn get_api_url_with_options(
+        base_url: String,
+        model: String,
+        options_json: Option<String>,
+    ) -> Result<String, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        Ok(endpoint::api_url(&base_url, &model, &options))
+    }
 
-        // Check for tool call delta
-        if let Some(tool_calls) = delta["tool_calls"].as_array() {
-            if !tool_calls.is_empty() {
-                let tc = &tool_calls[0];
-                // Extract the index from the tool call (OpenAI sends this)
-                let index = tc["index"].as_u64().map(|i| i as u32);
-                let id = tc["id"].as_str().map(|s| s.to_string());
-                let name = tc["function"]["name"].as_str().map(|s| s.to_string());
-                let arguments = tc["function"]["arguments"].as_str().map(|s| s.to_string());
-
-                // Only return if we have meaningful data
-                if id.is_some() || name.is_some() || arguments.is_some() {
-                    return Some(WitContentDelta {
-                        delta_type: "tool_call".to_string(),
-                        content: None,
-                        reasoning: None,
-                        tool_call_index: index,
-                        tool_call: Some(WitToolCall {
-                            id: id.unwrap_or_default(),
-                            name: name.unwrap_or_default(),
-                            arguments: arguments.unwrap_or_default(),
-                        }),
-                        error: None,
-                    });
-                }
-            }
-        }
+    /// Get HTTP headers for a request
+    fn get_request_headers(
+        config: WitConfig,
+        _model: String,
+        streaming: bool,
+        options_json: Option<String>,
+    ) -> Result<Vec<(String, String)>, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        Ok(endpoint::request_headers(
+            &config.base_url,
+            &config.api_key,
+            streaming,
+            &options,
+        ))
+    }
 
-        None
+    /// Check if streaming is supported
+    fn supports_streaming(model: String) -> bool {
+        models::capabilities(&model).1.streaming
     }
 
-    /// Get API URL for OpenAI
-    fn get_api_url(base_url: String, _model: String) -> String {
-        let base = base_url.trim_end_matches('/');
-        format!("{}/chat/completions", base)
+    /// Capabilities of a model from the catalog
+    fn get_model_info(model: String) -> WitModelInfo {
+        let (pattern, capabilities) = models::capabilities(&model);
+        wit_model_info(pattern, capabilities)
     }
 
-    /// Check if streaming is supported
-    fn supports_streaming(_model: String) -> bool {
-        // All OpenAI models support streaming
-        true
+    /// Override or extend the model catalog
+    fn set_model_catalog(catalog_json: String) -> Result<(), ProviderError> {
+        models::set_overrides(&catalog_json)
     }
 
     /// Format request from JSON (handles complex messages with tool_call_id, etc.)
@@ -341,115 +270,57 @@ impl ProviderGuest for OpenAIProvider {
         temperature: f32,
         enable_streaming: bool,
     ) -> Result<String, ProviderError> {
-        // Parse messages from JSON
-        let messages: Vec<Value> =
-            serde_json::from_str(&messages_json).map_err(|e| ProviderError {
-                message: format!("Failed to parse messages JSON: {}", e),
-                code: Some("JSON_PARSE_ERROR".to_string()),
-                http_status: None,
-                response_body: Some(messages_json.clone()),
-                is_retryable: Some(false),
-                retry_after: None,
-            })?;
-
-        // Convert InternalMessage format to OpenAI format
-        let openai_messages: Vec<Value> = messages
-            .into_iter()
-            .map(|msg| {
-                let role = msg["role"].as_str().unwrap_or("user");
-
-                // Handle different message types
-                match role {
-                    "tool" => {
-                        // Tool result message — extract content from Blocks array or plain string
-                        let tool_call_id = msg["tool_call_id"].as_str().unwrap_or("");
-                        let content = if let Some(blocks) = msg["content"].as_array() {
-                            // InternalMessage with MessageContent::Blocks: extract tool_result content
-                            blocks.iter()
-                                .find(|b| b["type"].as_str() == Some("tool_result"))
-                                .and_then(|b| b["content"].as_str())
-                                .unwrap_or("")
-                                .to_string()
-                        } else if let Some(s) = msg["content"].as_str() {
-                            s.to_string()
-                        } else {
-                            String::new()
-                        };
-                        json!({
-                            "role": "tool",
-                            "tool_call_id": tool_call_id,
-                            "content": content
-                        })
-                    }
-                    "assistant" => {
-                        // Assistant message — may have text content and/or tool_calls.
-                        // Content can be a plain string OR an array of typed blocks
-                        // (MessageContent::Blocks via #[serde(untagged)]).
-                        let mut assistant_msg = json!({
-                            "role": "assistant",
-                            "content": null,
-                        });
-
-                        let mut text_content = String::new();
-                        let mut tool_calls: Vec<Value> = Vec::new();
-
-                        if let Some(blocks) = msg["content"].as_array() {
-                            // Parse structured content blocks
-                            for block in blocks {
-                                match block["type"].as_str() {
-                                    Some("text") => {
-                                        if let Some(t) = block["text"].as_str() {
-                                            text_content = t.to_string();
-                                        }
-                                    }
-                                    Some("tool_use") => {
-                                        let id = block["id"].as_str().unwrap_or("").to_string();
-                                        let name = block["name"].as_str().unwrap_or("").to_string();
-                                        let args = block.get("input")
-                                            .map(|v| serde_json::to_string(v).unwrap_or_default())
-                                            .unwrap_or_default();
-                                        tool_calls.push(json!({
-                                            "id": id,
-                                            "type": "function",
-                                            "function": {
-                                                "name": name,
-                                                "arguments": args
-                                            }
-                                        }));
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        } else if let Some(s) = msg["content"].as_str() {
-                            text_content = s.to_string();
-                        }
+        Self::format_request_with_options(
+            messages_json,
+            model,
+            tools_json,
+            tool_choice_json,
+            max_tokens,
+            temperature,
+            enable_streaming,
+            None,
+        )
+    }
 
-                        // OpenAI requires content to be null when tool_calls are present
-                        if !tool_calls.is_empty() {
-                            assistant_msg["tool_calls"] = json!(tool_calls);
-                        } else if !text_content.is_empty() {
-                            assistant_msg["content"] = json!(text_content);
-                        }
+    /// Format request from JSON with additional request options
+    fn format_request_with_options(
+        messages_json: String,
+        model: String,
+        tools_json: Option<String>,
+        tool_choice_json: Option<String>,
+        max_tokens: Option<u32>,
+        temperature: f32,
+        enable_streaming: bool,
+        options_json: Option<String>,
+    ) -> Result<String, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        options::check_range("temperature", Some(f64::from(temperature)), 0.0, 2.0)?;
+
+        let mut messages = parse_messages_json(&messages_json)?;
+
+        // Drop the oldest turns when the prompt is over the context budget;
+        // the report is only returned by fit-messages
+        if let Some(mode @ ("drop" | "summarize")) = options.fit_to_context.as_deref() {
+            messages = fit::fit_request(
+                messages,
+                &model,
+                tools_json.as_deref(),
+                max_tokens,
+                &options,
+                mode == "summarize",
+            )?
+            .0;
+        }
 
-                        assistant_msg
-                    }
-                    _ => {
-                        // Regular user/system message
-                        let content = match &msg["content"] {
-                            Value::String(s) => s.clone(),
-                            Value::Object(_) | Value::Array(_) => {
-                                serde_json::to_string(&msg["content"]).unwrap_or_default()
-                            }
-                            _ => String::new(),
-                        };
-                        json!({
-                            "role": role,
-                            "content": content
-                        })
-                    }
-                }
-            })
-            .collect();
+        // Convert InternalMessage format to OpenAI format
+        let style = options.history_style(&model);
+        let openai_messages = match options.validate_history.as_deref() {
+            Some("strict") | Some("repair") => conversation::validate(
+                messages::to_openai_messages_indexed(messages, &style),
+                options.validate_history.as_deref() == Some("strict"),
+            )?,
+            _ => messages::to_openai_messages(messages, &style),
+        };
 
         // Build request body
         let mut body = json!({
@@ -463,9 +334,10 @@ impl ProviderGuest for OpenAIProvider {
             body["max_tokens"] = json!(tokens);
         }
 
-        // Add streaming if enabled
+        // Add streaming if enabled, asking for the final usage-only chunk
         if enable_streaming {
             body["stream"] = json!(true);
+            body["stream_options"] = json!({ "include_usage": true });
         }
 
         // Add tools if provided
@@ -515,6 +387,35 @@ impl ProviderGuest for OpenAIProvider {
             }
         }
 
+        // Backends without native tool calling get the tools in the prompt
+        if options.prompt_tools() {
+            prompt_tools::apply(&mut body);
+        }
+
+        // Servers that predate `tools` take `functions` / `function_call`
+        if options.legacy_functions() {
+            functions::apply(&mut body);
+        }
+
+        // Add sampling parameters from options
+        options.apply_sampling(&mut body);
+
+        // Add structured output format if requested
+        if let Some(format) = &options.response_format {
+            body["response_format"] = format.clone();
+        }
+
+        // Adapt token limit, sampling fields and roles to the model
+        models::lookup(&model).shape_request(&mut body, options.reasoning_effort.as_deref());
+
+        // Vendor quirks (tool call id format, rejected fields, ...)
+        backends::select(&model, &options).shape_request(&mut body);
+
+        // Models served only by /v1/responses (or the api option) take a Responses body
+        if options.uses_responses_api(&model) {
+            body = responses::from_chat_body(&body, &options)?;
+        }
+
         serde_json::to_string(&body).map_err(|e| ProviderError {
             message: format!("Failed to serialize request: {}", e),
             code: Some("SERIALIZATION_ERROR".to_string()),
@@ -524,33 +425,323 @@ impl ProviderGuest for OpenAIProvider {
             retry_after: None,
         })
     }
+
+    /// Parse response, validating it against the request options
+    fn parse_response_with_options(
+        body: String,
+        model: String,
+        options_json: Option<String>,
+    ) -> Result<WitAssistantMessage, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        let (id, mut choice, usage) = parse_body(body)?;
+        if options.think_tags(&model) {
+            think::extract_into(&mut choice, options.think_opened(), false);
+        }
+        if options.prompt_tools() {
+            prompt_tools::extract_into(&mut choice, &options.tool_names, false);
+        }
+        backends::select(&model, &options).shape_choice(&mut choice);
+        let message = assistant_message(id, choice, usage);
+        check_structured_output(&message, &options)?;
+        Ok(message)
+    }
+
+    /// Format an embeddings request
+    fn format_embeddings_request(
+        inputs: Vec<String>,
+        model: String,
+        dimensions: Option<u32>,
+        encoding_format: Option<String>,
+    ) -> Result<String, ProviderError> {
+        embeddings::format_request(&inputs, &model, dimensions, encoding_format.as_deref())
+    }
+
+    /// Parse an embeddings response
+    fn parse_embeddings_response(body: String) -> Result<WitEmbeddingsResponse, ProviderError> {
+        embeddings::parse_response(&body)
+    }
+
+    /// Get the embeddings URL for a model
+    fn get_embeddings_url(
+        base_url: String,
+        model: String,
+        options_json: Option<String>,
+    ) -> Result<String, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        Ok(endpoint::embeddings_url(&base_url, &model, &options))
+    }
+
+    /// Get the model listing URL
+    fn get_models_url(
+        base_url: String,
+        options_json: Option<String>,
+    ) -> Result<String, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        Ok(endpoint::models_url(&base_url, &options))
+    }
+
+    /// Parse a model listing
+    fn parse_models_response(body: String) -> Result<Vec<WitListedModel>, ProviderError> {
+        model_list::parse(&body)
+    }
+
+    /// Trim history to the model's context window
+    fn fit_messages(
+        messages_json: String,
+        tools_json: Option<String>,
+        model: String,
+        max_tokens: Option<u32>,
+        options_json: Option<String>,
+    ) -> Result<WitFitResult, ProviderError> {
+        let options = RequestOptions::parse(options_json.as_deref())?;
+        let messages = parse_messages_json(&messages_json)?;
+        let summarize = options.fit_to_context.as_deref() == Some("summarize");
+
+        let (messages, report) = fit::fit_request(
+            messages,
+            &model,
+            tools_json.as_deref(),
+            max_tokens,
+            &options,
+            summarize,
+        )?;
+        Ok(WitFitResult {
+            messages_json: serde_json::to_string(&messages).unwrap_or_default(),
+            dropped: report.dropped,
+            summarized: report.summarized,
+            prompt_tokens: report.prompt_tokens,
+        })
+    }
+
+    /// Estimate the prompt tokens of a request
+    fn count_tokens(
+        messages_json: String,
+        tools_json: Option<String>,
+        model: String,
+    ) -> Result<WitTokenCount, ProviderError> {
+        let messages = parse_messages_json(&messages_json)?;
+        let style = RequestOptions::default().history_style(&model);
+        let per_message = tokens::per_message(&messages, &style);
+
+        // Tools are formatted the same lenient way: unparseable means none
+        let tools: Vec<Value> = tools_json
+            .and_then(|t| serde_json::from_str(&t).ok())
+            .unwrap_or_default();
+        let tools = tokens::tools_tokens(&tools);
+
+        let capabilities = models::capabilities(&model).1;
+        Ok(WitTokenCount {
+            total: per_message.iter().sum::<u32>() + tools + tokens::REPLY_PRIMING,
+            messages: per_message,
+            tools,
+            context_window: capabilities.context_window,
+            max_output_tokens: capabilities.max_output_tokens,
+        })
+    }
+}
+
+/// Parse a JSON array of InternalMessage values
+fn parse_messages_json(messages_json: &str) -> Result<Vec<Value>, ProviderError> {
+    serde_json::from_str(messages_json).map_err(|e| ProviderError {
+        message: format!("Failed to parse messages JSON: {}", e),
+        code: Some("JSON_PARSE_ERROR".to_string()),
+        http_status: None,
+        response_body: Some(messages_json.to_string()),
+        is_retryable: Some(false),
+        retry_after: None,
+    })
+}
+
+fn wit_model_info(pattern: String, capabilities: models::Capabilities) -> WitModelInfo {
+    WitModelInfo {
+        pattern,
+        context_window: capabilities.context_window,
+        max_output_tokens: capabilities.max_output_tokens,
+        vision: capabilities.vision,
+        tools: capabilities.tools,
+        parallel_tool_calls: capabilities.parallel_tool_calls,
+        reasoning: capabilities.reasoning,
+        json_schema: capabilities.json_schema,
+        streaming: capabilities.streaming,
+    }
+}
+
+/// Parse a Chat Completions or Responses API body into its id, first choice
+/// and usage
+fn parse_body(body: String) -> Result<(Option<String>, Choice, Option<WitUsage>), ProviderError> {
+    // Error bodies (`{"error":{...}}`) get classified instead of failing on `choices`
+    if let Ok(json) = serde_json::from_str::<Value>(&body) {
+        if errors::is_error_body(&json) {
+            return Err(errors::classify(None, &[], &body));
+        }
+        if responses::is_response(&json) {
+            let (choice, usage) = responses::parse(&json, &body)?;
+            let id = json["id"].as_str().map(str::to_string);
+            return Ok((id, choice, usage.map(wit_usage)));
+        }
+    }
+
+    let response: OpenAIResponse = serde_json::from_str(&body).map_err(|e| ProviderError {
+        message: format!("Failed to parse response: {}", e),
+        code: Some("PARSE_ERROR".to_string()),
+        http_status: None,
+        response_body: Some(body.clone()),
+        is_retryable: Some(false),
+        retry_after: None,
+    })?;
+
+    let usage = response.usage.map(wit_usage);
+    let Some(mut choice) = response.choices.into_iter().next() else {
+        return Err(ProviderError {
+            message: "No choices in response".to_string(),
+            code: Some("EMPTY_RESPONSE".to_string()),
+            http_status: None,
+            response_body: Some(body),
+            is_retryable: Some(false),
+            retry_after: None,
+        });
+    };
+
+    functions::extract_into(&mut choice, response.id.as_deref());
+    Ok((response.id, choice, usage))
+}
+
+/// Check that content produced under a JSON response format is valid JSON
+/// and, for `json_schema`, satisfies the schema. Refusals and tool-call turns
+/// carry no content and are left to the caller.
+fn check_structured_output(
+    message: &WitAssistantMessage,
+    options: &RequestOptions,
+) -> Result<(), ProviderError> {
+    let Some(content) = message.content.as_ref().filter(|_| options.expects_json()) else {
+        return Ok(());
+    };
+
+    // Cut off at the token limit: the JSON is incomplete, not wrong
+    if message.finish_reason.as_deref() == Some("length") {
+        return Err(ProviderError {
+            message:
+                "Response was truncated at the output token limit; structured output is incomplete"
+                    .to_string(),
+            code: Some("OUTPUT_TRUNCATED".to_string()),
+            http_status: None,
+            response_body: Some(content.clone()),
+            is_retryable: Some(false),
+            retry_after: None,
+        });
+    }
+
+    let value: Value = serde_json::from_str(content).map_err(|e| ProviderError {
+        message: format!("Response content is not valid JSON: {}", e),
+        code: Some("INVALID_JSON_OUTPUT".to_string()),
+        http_status: None,
+        response_body: Some(content.clone()),
+        is_retryable: Some(false),
+        retry_after: None,
+    })?;
+
+    if let Some(schema) = options.response_schema() {
+        let violations = schema::validate(&value, schema);
+        if !violations.is_empty() {
+            return Err(ProviderError {
+                message: format!(
+                    "Response does not match the JSON schema: {}",
+                    violations.join("; ")
+                ),
+                code: Some("SCHEMA_VALIDATION_ERROR".to_string()),
+                http_status: None,
+                response_body: Some(content.clone()),
+                is_retryable: Some(false),
+                retry_after: None,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert a parsed OpenAI choice into the WIT assistant message.
+/// Shared by `parse_response` and the stream accumulator so both agree.
+fn assistant_message(
+    id: Option<String>,
+    choice: Choice,
+    usage: Option<WitUsage>,
+) -> WitAssistantMessage {
+    let message = choice.message;
+
+    // Extract content (servers send "" alongside tool calls as often as null)
+    let content = message.content.filter(|c| !c.is_empty());
+
+    // Extract reasoning content
+    let reasoning = message.reasoning_content;
+
+    // Extract tool calls
+    let tool_calls: Vec<WitToolCall> = message
+        .tool_calls
+        .unwrap_or_default()
+        .into_iter()
+        .map(|call| WitToolCall {
+            id: call.id,
+            name: call.function.name,
+            arguments: call.function.arguments,
+        })
+        .collect();
+
+    WitAssistantMessage {
+        id,
+        content,
+        tool_calls,
+        reasoning,
+        usage,
+        finish_reason: choice.finish_reason,
+        refusal: message.refusal.filter(|r| !r.is_empty()),
+    }
+}
+
+/// Convert the API usage object into the WIT usage record
+fn wit_usage(usage: OpenAIUsage) -> WitUsage {
+    WitUsage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        total_tokens: usage.total_tokens,
+        // DeepSeek reports cache hits as a top-level field
+        cached_tokens: usage
+            .prompt_tokens_details
+            .and_then(|d| d.cached_tokens)
+            .or(usage.prompt_cache_hit_tokens),
+        reasoning_tokens: usage
+            .completion_tokens_details
+            .and_then(|d| d.reasoning_tokens),
+    }
 }
 
 // ===== OpenAI Response Types =====
 
 #[derive(Debug, Deserialize)]
 struct OpenAIResponse {
-    #[allow(dead_code)]
     id: Option<String>,
     choices: Vec<Choice>,
+    usage: Option<OpenAIUsage>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Choice {
     message: ResponseMessage,
-    #[allow(dead_code)]
     finish_reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct ResponseMessage {
     #[allow(dead_code)]
     role: String,
     content: Option<String>,
-    #[allow(dead_code)]
     tool_calls: Option<Vec<OpenAIToolCall>>,
     /// Reasoning/thinking content (for thinking models like GLM, DeepSeek)
     reasoning_content: Option<String>,
+    /// Refusal text (structured outputs / safety refusals)
+    refusal: Option<String>,
+    /// Legacy single function call (`functions` protocol)
+    function_call: Option<FunctionCall>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -568,6 +759,29 @@ struct FunctionCall {
     arguments: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct OpenAIUsage {
+    #[serde(default)]
+    prompt_tokens: u32,
+    #[serde(default)]
+    completion_tokens: u32,
+    #[serde(default)]
+    total_tokens: u32,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+    completion_tokens_details: Option<CompletionTokensDetails>,
+    prompt_cache_hit_tokens: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PromptTokensDetails {
+    cached_tokens: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionTokensDetails {
+    reasoning_tokens: Option<u32>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -610,6 +824,30 @@ mod tests {
             OpenAIProvider::parse_response(response.to_string(), "gpt-4o".to_string()).unwrap();
         assert_eq!(result.content, Some("Hello! How can I help?".to_string()));
         assert!(result.tool_calls.is_empty());
+        assert_eq!(result.finish_reason, Some("stop".to_string()));
+    }
+
+    #[test]
+    fn test_parse_refusal_response() {
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "refusal": "I'm sorry, I can't help with that."
+                },
+                "finish_reason": "content_filter"
+            }]
+        });
+
+        let result =
+            OpenAIProvider::parse_response(response.to_string(), "gpt-4o".to_string()).unwrap();
+        assert_eq!(result.content, None);
+        assert_eq!(
+            result.refusal,
+            Some("I'm sorry, I can't help with that.".to_string())
+        );
+        assert_eq!(result.finish_reason, Some("content_filter".to_string()));
     }
 
     #[test]
@@ -690,4 +928,400 @@ mod tests {
         );
         assert_eq!(url, "https://api.openai.com/v1/chat/completions");
     }
+
+    #[test]
+    fn test_parse_usage() {
+        let response = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": "Hi" },
+                "finish_reason": "stop"
+            }],
+            "usage": {
+                "prompt_tokens": 120,
+                "completion_tokens": 30,
+                "total_tokens": 150,
+                "prompt_tokens_details": { "cached_tokens": 100 },
+                "completion_tokens_details": { "reasoning_tokens": 12 }
+            }
+        });
+
+        let result =
+            OpenAIProvider::parse_response(response.to_string(), "gpt-4o".to_string()).unwrap();
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 120);
+        assert_eq!(usage.completion_tokens, 30);
+        assert_eq!(usage.total_tokens, 150);
+        assert_eq!(usage.cached_tokens, Some(100));
+        assert_eq!(usage.reasoning_tokens, Some(12));
+    }
+
+    #[test]
+    fn test_streaming_request_includes_usage() {
+        let messages = json!([{ "role": "user", "content": "Hi" }]).to_string();
+
+        let body = OpenAIProvider::format_request_from_json(
+            messages.clone(),
+            "gpt-4o".to_string(),
+            None,
+            None,
+            None,
+            0.7,
+            true,
+        )
+        .unwrap();
+        let parsed: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["stream_options"]["include_usage"], true);
+
+        let body = OpenAIProvider::format_request_from_json(
+            messages,
+            "gpt-4o".to_string(),
+            None,
+            None,
+            None,
+            0.7,
+            false,
+        )
+        .unwrap();
+        let parsed: Value = serde_json::from_str(&body).unwrap();
+        assert!(parsed.get("stream_options").is_none());
+    }
+
+    fn extraction_options() -> String {
+        json!({
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "person",
+                    "strict": true,
+                    "schema": {
+                        "type": "object",
+                        "properties": { "name": { "type": "string" } },
+                        "required": ["name"],
+                        "additionalProperties": false
+                    }
+                }
+            }
+        })
+        .to_string()
+    }
+
+    fn text_response(content: &str) -> String {
+        json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop"
+            }]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_temperature_out_of_range_rejected() {
+        for temperature in [-0.5, 2.5, f32::NAN] {
+            let err = OpenAIProvider::format_request_from_json(
+                json!([{ "role": "user", "content": "Hi" }]).to_string(),
+                "gpt-4o".to_string(),
+                None,
+                None,
+                None,
+                temperature,
+                false,
+            )
+            .unwrap_err();
+            assert_eq!(err.code, Some("INVALID_PARAMETER".to_string()));
+            assert!(err.message.contains("temperature"), "{}", err.message);
+        }
+        assert!(OpenAIProvider::format_request_from_json(
+            json!([{ "role": "user", "content": "Hi" }]).to_string(),
+            "gpt-4o".to_string(),
+            None,
+            None,
+            None,
+            2.0,
+            false,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_format_response_format() {
+        let body = OpenAIProvider::format_request_with_options(
+            json!([{ "role": "user", "content": "Extract" }]).to_string(),
+            "gpt-4o".to_string(),
+            None,
+            None,
+            None,
+            0.0,
+            false,
+            Some(extraction_options()),
+        )
+        .unwrap();
+        let parsed: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["response_format"]["type"], "json_schema");
+        assert_eq!(parsed["response_format"]["json_schema"]["strict"], true);
+
+        let err = OpenAIProvider::format_request_with_options(
+            json!([]).to_string(),
+            "gpt-4o".to_string(),
+            None,
+            None,
+            None,
+            0.0,
+            false,
+            Some(r#"{"response_format":{"type":"yaml"}}"#.to_string()),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, Some("INVALID_PARAMETER".to_string()));
+    }
+
+    #[test]
+    fn test_structured_output_validation() {
+        let ok = OpenAIProvider::parse_response_with_options(
+            text_response(r#"{"name":"Ada"}"#),
+            "gpt-4o".to_string(),
+            Some(extraction_options()),
+        );
+        assert!(ok.is_ok());
+
+        let err = OpenAIProvider::parse_response_with_options(
+            text_response(r#"{"name":"Ada""#),
+            "gpt-4o".to_string(),
+            Some(extraction_options()),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, Some("INVALID_JSON_OUTPUT".to_string()));
+
+        let err = OpenAIProvider::parse_response_with_options(
+            text_response(r#"{"name":1,"age":3}"#),
+            "gpt-4o".to_string(),
+            Some(extraction_options()),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, Some("SCHEMA_VALIDATION_ERROR".to_string()));
+        assert!(err.message.contains("/name: expected string"));
+        assert!(err.message.contains("'age' is not allowed"));
+
+        // Without options the same content is returned untouched
+        let plain = OpenAIProvider::parse_response_with_options(
+            text_response("not json"),
+            "gpt-4o".to_string(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(plain.content, Some("not json".to_string()));
+
+        // Cut off at max_tokens: reported as truncation, not a schema error
+        let truncated = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": r#"{"name":"Ad"# },
+                "finish_reason": "length"
+            }]
+        });
+        let err = OpenAIProvider::parse_response_with_options(
+            truncated.to_string(),
+            "gpt-4o".to_string(),
+            Some(extraction_options()),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, Some("OUTPUT_TRUNCATED".to_string()));
+    }
+
+    #[test]
+    fn test_reasoning_model_request() {
+        let messages = json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Hi" }
+        ]);
+        let body = OpenAIProvider::format_request_with_options(
+            messages.to_string(),
+            "o3-mini".to_string(),
+            None,
+            None,
+            Some(2048),
+            0.2,
+            false,
+            Some(r#"{"reasoning_effort":"low","top_p":0.5}"#.to_string()),
+        )
+        .unwrap();
+        let parsed: Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(parsed["max_completion_tokens"], 2048);
+        assert!(parsed.get("max_tokens").is_none());
+        assert!(parsed.get("temperature").is_none());
+        assert!(parsed.get("top_p").is_none());
+        assert_eq!(parsed["reasoning_effort"], "low");
+        assert_eq!(parsed["messages"][0]["role"], "developer");
+    }
+
+    #[test]
+    fn test_responses_api_request_and_response() {
+        let messages = json!([{ "role": "user", "content": "Hi" }]);
+        let body = OpenAIProvider::format_request_with_options(
+            messages.to_string(),
+            "o3-pro".to_string(),
+            None,
+            None,
+            Some(1000),
+            1.0,
+            false,
+            Some(r#"{"reasoning_effort":"high","previous_response_id":"resp_0"}"#.to_string()),
+        )
+        .unwrap();
+        let parsed: Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(parsed["input"][0]["content"], "Hi");
+        assert_eq!(parsed["max_output_tokens"], 1000);
+        assert_eq!(parsed["reasoning"]["effort"], "high");
+        assert_eq!(parsed["previous_response_id"], "resp_0");
+        assert!(parsed.get("messages").is_none());
+
+        let response = json!({
+            "id": "resp_1",
+            "object": "response",
+            "status": "completed",
+            "output": [
+                { "type": "reasoning", "id": "rs_1", "summary": [
+                    { "type": "summary_text", "text": "Greeting." }
+                ]},
+                { "type": "message", "role": "assistant", "content": [
+                    { "type": "output_text", "text": "Hello!", "annotations": [] }
+                ]}
+            ],
+            "usage": {
+                "input_tokens": 5,
+                "input_tokens_details": { "cached_tokens": 0 },
+                "output_tokens": 20,
+                "output_tokens_details": { "reasoning_tokens": 12 },
+                "total_tokens": 25
+            }
+        });
+        let message =
+            OpenAIProvider::parse_response(response.to_string(), "o3-pro".to_string()).unwrap();
+
+        assert_eq!(message.id, Some("resp_1".to_string()));
+        assert_eq!(message.content, Some("Hello!".to_string()));
+        assert_eq!(message.reasoning, Some("Greeting.".to_string()));
+        assert_eq!(message.finish_reason, Some("stop".to_string()));
+        let usage = message.usage.unwrap();
+        assert_eq!(usage.total_tokens, 25);
+        assert_eq!(usage.reasoning_tokens, Some(12));
+    }
+
+    #[test]
+    fn test_responses_api_failed_response() {
+        let response = json!({
+            "id": "resp_2",
+            "object": "response",
+            "status": "failed",
+            "error": { "code": "rate_limit_exceeded", "message": "Slow down" },
+            "output": []
+        });
+        let err =
+            OpenAIProvider::parse_response(response.to_string(), "o3-pro".to_string()).unwrap_err();
+        assert_eq!(err.code, Some("RATE_LIMITED".to_string()));
+        assert_eq!(err.is_retryable, Some(true));
+    }
+
+    #[test]
+    fn test_model_catalog() {
+        assert!(OpenAIProvider::supports_streaming("gpt-4o".to_string()));
+        assert!(!OpenAIProvider::supports_streaming("o3-pro".to_string()));
+
+        let info = OpenAIProvider::get_model_info("openrouter/openai/gpt-4.1-mini".to_string());
+        assert_eq!(info.pattern, "gpt-4.1*");
+        assert_eq!(info.context_window, 1_047_576);
+        assert!(info.vision && info.tools && info.json_schema);
+
+        OpenAIProvider::set_model_catalog(r#"[{"pattern":"qwen3*","reasoning":true}]"#.to_string())
+            .unwrap();
+        assert!(OpenAIProvider::get_model_info("qwen3-32b".to_string()).reasoning);
+
+        let metadata: Value =
+            serde_json::from_str(&OpenAIProvider::get_provider_metadata()).unwrap();
+        assert!(metadata["features"].get("vision").is_none());
+        let models = metadata["models"].as_array().unwrap();
+        assert_eq!(models[0]["pattern"], "qwen3*");
+        assert!(models
+            .iter()
+            .any(|m| m["pattern"] == "gpt-4o*" && m["context_window"] == 128_000));
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        let messages = json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Weather in Paris?" },
+            { "role": "assistant", "content": [
+                { "type": "tool_use", "id": "call_1", "name": "get_weather", "input": { "city": "Paris" } }
+            ]},
+            { "role": "tool", "content": [
+                { "type": "tool_result", "tool_use_id": "call_1", "content": "sunny" }
+            ]}
+        ]);
+        let tools = json!([{
+            "name": "get_weather",
+            "description": "Get the weather",
+            "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+        }]);
+
+        let count = OpenAIProvider::count_tokens(
+            messages.to_string(),
+            Some(tools.to_string()),
+            "gpt-4o".to_string(),
+        )
+        .unwrap();
+        assert_eq!(count.messages.len(), 4);
+        assert!(count.messages.iter().all(|&n| n > 3));
+        assert!(count.tools > 0);
+        assert_eq!(
+            count.total,
+            count.messages.iter().sum::<u32>() + count.tools + 3
+        );
+        assert_eq!(count.context_window, 128_000);
+
+        let err = OpenAIProvider::count_tokens("not json".to_string(), None, "gpt-4o".to_string())
+            .unwrap_err();
+        assert_eq!(err.code, Some("JSON_PARSE_ERROR".to_string()));
+    }
+
+    #[test]
+    fn test_fit_to_context() {
+        OpenAIProvider::set_model_catalog(
+            r#"[{"pattern":"tiny*","context_window":300,"max_output_tokens":100}]"#.to_string(),
+        )
+        .unwrap();
+        let messages = json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Summarise this: ".repeat(100) },
+            { "role": "assistant", "content": "Done." },
+            { "role": "user", "content": "Thanks. Next?" }
+        ])
+        .to_string();
+
+        let fitted = OpenAIProvider::fit_messages(
+            messages.clone(),
+            None,
+            "tiny-model".to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fitted.dropped, vec![1, 2]);
+        assert!(fitted.prompt_tokens <= 200);
+
+        let body = OpenAIProvider::format_request_with_options(
+            messages,
+            "tiny-model".to_string(),
+            None,
+            None,
+            None,
+            0.0,
+            false,
+            Some(r#"{"fit_to_context":"drop"}"#.to_string()),
+        )
+        .unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(body["messages"][1]["content"], "Thanks. Next?");
+    }
 }
@@ -0,0 +1,511 @@
+//! InternalMessage → OpenAI chat message conversion
+//!
+//! Messages arrive as ABK `InternalMessage` JSON, whose content is either a
+//! plain string or an array of typed blocks (`MessageContent::Blocks` via
+//! `#[serde(untagged)]`). This module turns them into Chat Completions messages.
+//! One InternalMessage may expand to several: every `tool_result` block, in a
+//! `tool` or a `user` message, becomes its own `tool` message.
+
+use serde_json::{json, Value};
+
+/// Per-backend choices for replaying assistant history
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct HistoryStyle {
+    /// Where `thinking`/`reasoning` blocks go: `strip`, or the assistant
+    /// message field to echo them in (`reasoning_content` or `reasoning`)
+    pub reasoning: &'static str,
+    /// Keep assistant text next to `tool_calls`; off only for servers that
+    /// reject content on tool-call messages
+    pub content_with_tool_calls: bool,
+}
+
+impl Default for HistoryStyle {
+    fn default() -> Self {
+        Self {
+            reasoning: "strip",
+            content_with_tool_calls: true,
+        }
+    }
+}
+
+/// Convert a list of InternalMessage values to OpenAI chat messages
+pub(crate) fn to_openai_messages(messages: Vec<Value>, style: &HistoryStyle) -> Vec<Value> {
+    messages
+        .into_iter()
+        .flat_map(|msg| to_openai_message(msg, style))
+        .collect()
+}
+
+/// Like `to_openai_messages`, pairing each OpenAI message with the index of
+/// the InternalMessage it came from
+pub(crate) fn to_openai_messages_indexed(
+    messages: Vec<Value>,
+    style: &HistoryStyle,
+) -> Vec<(usize, Value)> {
+    messages
+        .into_iter()
+        .enumerate()
+        .flat_map(|(index, msg)| {
+            to_openai_message(msg, style)
+                .into_iter()
+                .map(move |converted| (index, converted))
+        })
+        .collect()
+}
+
+fn to_openai_message(msg: Value, style: &HistoryStyle) -> Vec<Value> {
+    let role = msg["role"].as_str().unwrap_or("user");
+
+    // Handle different message types
+    match role {
+        "tool" => {
+            // Tool result message — one OpenAI tool message per tool_result block
+            let tool_call_id = msg["tool_call_id"].as_str().unwrap_or("");
+            let results: Vec<Value> = msg["content"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|b| b["type"] == "tool_result")
+                .map(|b| tool_message(b, tool_call_id))
+                .collect();
+            if !results.is_empty() {
+                return results;
+            }
+
+            // Plain string or unwrapped blocks are the result itself
+            vec![json!({
+                "role": "tool",
+                "tool_call_id": tool_call_id,
+                "content": flatten_content(&msg["content"])
+            })]
+        }
+        "assistant" => {
+            // Assistant message — may have text content and/or tool_calls.
+            let mut assistant_msg = json!({
+                "role": "assistant",
+                "content": null,
+            });
+
+            let mut text_blocks: Vec<&str> = Vec::new();
+            let mut reasoning = String::new();
+            let mut tool_calls: Vec<Value> = Vec::new();
+
+            if let Some(blocks) = msg["content"].as_array() {
+                // Parse structured content blocks
+                for block in blocks {
+                    match block["type"].as_str() {
+                        Some("text") => {
+                            if let Some(t) = block["text"].as_str() {
+                                text_blocks.push(t);
+                            }
+                        }
+                        Some("thinking") | Some("reasoning") => {
+                            if let Some(t) = thinking_text(block) {
+                                reasoning.push_str(t);
+                            }
+                        }
+                        Some("tool_use") => {
+                            let id = block["id"].as_str().unwrap_or("").to_string();
+                            let name = block["name"].as_str().unwrap_or("").to_string();
+                            let args = block
+                                .get("input")
+                                .map(|v| serde_json::to_string(v).unwrap_or_default())
+                                .unwrap_or_default();
+                            tool_calls.push(json!({
+                                "id": id,
+                                "type": "function",
+                                "function": {
+                                    "name": name,
+                                    "arguments": args
+                                }
+                            }));
+                        }
+                        _ => {}
+                    }
+                }
+            } else if let Some(s) = msg["content"].as_str() {
+                text_blocks.push(s);
+            }
+            let text_content = text_blocks.join("\n");
+
+            // Text before a tool call is the model's own preamble; OpenAI accepts
+            // it next to tool_calls, so only drop it for servers that do not
+            let keep_text = tool_calls.is_empty() || style.content_with_tool_calls;
+            if !text_content.is_empty() && keep_text {
+                assistant_msg["content"] = json!(text_content);
+            }
+            if !tool_calls.is_empty() {
+                assistant_msg["tool_calls"] = json!(tool_calls);
+            }
+
+            // Some backends need earlier reasoning echoed back, others reject it
+            if style.reasoning != "strip" && !reasoning.is_empty() {
+                assistant_msg[style.reasoning] = json!(reasoning);
+            }
+
+            vec![assistant_msg]
+        }
+        _ => {
+            // Regular user/system message. Anthropic-style user turns may carry
+            // tool results; those must follow the assistant turn as tool messages.
+            let Some(blocks) = msg["content"].as_array() else {
+                return vec![json!({
+                    "role": role,
+                    "content": user_content(&msg["content"])
+                })];
+            };
+            let (results, rest): (Vec<&Value>, Vec<&Value>) =
+                blocks.iter().partition(|b| b["type"] == "tool_result");
+
+            let mut converted: Vec<Value> = results.iter().map(|b| tool_message(b, "")).collect();
+            if !rest.is_empty() || converted.is_empty() {
+                let rest: Vec<Value> = rest.into_iter().cloned().collect();
+                converted.push(json!({
+                    "role": role,
+                    "content": user_content(&json!(rest))
+                }));
+            }
+            converted
+        }
+    }
+}
+
+/// OpenAI tool message for one `tool_result` block. Failed results are
+/// marked in the text since tool messages have no error flag.
+fn tool_message(block: &Value, fallback_id: &str) -> Value {
+    let tool_call_id = block["tool_use_id"]
+        .as_str()
+        .or_else(|| block["tool_call_id"].as_str())
+        .unwrap_or(fallback_id);
+
+    let mut content = flatten_content(&block["content"]);
+    if block["is_error"] == true {
+        content = format!("Error: {}", content);
+    }
+
+    json!({
+        "role": "tool",
+        "tool_call_id": tool_call_id,
+        "content": content
+    })
+}
+
+/// Tool result content as plain text: text blocks are joined, other blocks
+/// and structured values are serialized as JSON
+fn flatten_content(content: &Value) -> String {
+    match content {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .map(
+                |block| match (block["type"].as_str(), block["text"].as_str()) {
+                    (Some("text"), Some(text)) => text.to_string(),
+                    _ => match block {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    },
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("\n"),
+        other => other.to_string(),
+    }
+}
+
+/// Text of a thinking block: Anthropic `thinking`, or `reasoning`/`text`
+/// as stored from OpenAI-compatible responses. Redacted blocks have none.
+fn thinking_text(block: &Value) -> Option<&str> {
+    ["thinking", "reasoning", "text"]
+        .iter()
+        .find_map(|key| block[*key].as_str())
+}
+
+/// Convert user/system content into an OpenAI content value.
+/// Text-only content collapses to a plain string (accepted by every server);
+/// content with images or audio becomes an array of content parts.
+fn user_content(content: &Value) -> Value {
+    let blocks: Vec<&Value> = match content {
+        Value::String(s) => return json!(s),
+        Value::Array(blocks) => blocks.iter().collect(),
+        Value::Object(_) => vec![content],
+        _ => return json!(""),
+    };
+
+    let parts: Vec<Value> = blocks.into_iter().map(content_part).collect();
+
+    if parts.iter().all(|p| p["type"] == "text") {
+        let text: Vec<&str> = parts.iter().filter_map(|p| p["text"].as_str()).collect();
+        return json!(text.join("\n"));
+    }
+
+    json!(parts)
+}
+
+/// Convert one InternalMessage block into an OpenAI content part
+fn content_part(block: &Value) -> Value {
+    match block["type"].as_str() {
+        Some("text") => json!({
+            "type": "text",
+            "text": block["text"].as_str().unwrap_or("")
+        }),
+        Some("image") => image_part(block).unwrap_or_else(|| unsupported_part(block)),
+        Some("input_audio") | Some("audio") => {
+            audio_part(block).unwrap_or_else(|| unsupported_part(block))
+        }
+        // Already in OpenAI content-part format
+        Some("image_url") | Some("file") => block.clone(),
+        _ => unsupported_part(block),
+    }
+}
+
+/// Image block, either Anthropic-style with a `source` object
+/// (`{"type":"base64","media_type","data"}` or `{"type":"url","url"}`) or flat
+fn image_part(block: &Value) -> Option<Value> {
+    let source = block.get("source").unwrap_or(block);
+
+    let url = match source["url"].as_str() {
+        Some(url) => url.to_string(),
+        None => {
+            let data = source["data"].as_str()?;
+            if data.starts_with("data:") {
+                data.to_string()
+            } else {
+                let media_type = source["media_type"].as_str().unwrap_or("image/png");
+                format!("data:{};base64,{}", media_type, data)
+            }
+        }
+    };
+
+    let mut image_url = json!({ "url": url });
+    if let Some(detail) = block["detail"].as_str() {
+        image_url["detail"] = json!(detail);
+    }
+
+    Some(json!({
+        "type": "image_url",
+        "image_url": image_url
+    }))
+}
+
+/// Audio block: OpenAI `input_audio` (nested or flat) or an Anthropic-style
+/// `audio` block with a base64 `source`
+fn audio_part(block: &Value) -> Option<Value> {
+    let source = block
+        .get("input_audio")
+        .or_else(|| block.get("source"))
+        .unwrap_or(block);
+
+    let data = source["data"].as_str()?;
+    let format = source["format"]
+        .as_str()
+        .or_else(|| {
+            source["media_type"]
+                .as_str()
+                .and_then(|m| m.strip_prefix("audio/"))
+        })
+        .map(|f| if f == "mpeg" { "mp3" } else { f })
+        .unwrap_or("wav");
+
+    Some(json!({
+        "type": "input_audio",
+        "input_audio": {
+            "data": data,
+            "format": format
+        }
+    }))
+}
+
+/// Blocks with no OpenAI equivalent are passed to the model as JSON text
+fn unsupported_part(block: &Value) -> Value {
+    json!({
+        "type": "text",
+        "text": serde_json::to_string(block).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_blocks_become_content_parts() {
+        let messages = vec![json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "What is in these images?" },
+                {
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": "image/jpeg", "data": "AAAA" }
+                },
+                {
+                    "type": "image",
+                    "source": { "type": "url", "url": "https://example.com/cat.png" },
+                    "detail": "low"
+                }
+            ]
+        })];
+
+        let converted = to_openai_messages(messages, &HistoryStyle::default());
+        let content = &converted[0]["content"];
+        assert_eq!(content[0]["text"], "What is in these images?");
+        assert_eq!(content[1]["type"], "image_url");
+        assert_eq!(
+            content[1]["image_url"]["url"],
+            "data:image/jpeg;base64,AAAA"
+        );
+        assert_eq!(
+            content[2]["image_url"]["url"],
+            "https://example.com/cat.png"
+        );
+        assert_eq!(content[2]["image_url"]["detail"], "low");
+    }
+
+    #[test]
+    fn test_audio_blocks_become_input_audio() {
+        let messages = vec![json!({
+            "role": "user",
+            "content": [
+                { "type": "input_audio", "input_audio": { "data": "UklG", "format": "wav" } },
+                {
+                    "type": "audio",
+                    "source": { "type": "base64", "media_type": "audio/mpeg", "data": "SUQz" }
+                }
+            ]
+        })];
+
+        let converted = to_openai_messages(messages, &HistoryStyle::default());
+        let content = &converted[0]["content"];
+        assert_eq!(content[0]["input_audio"]["format"], "wav");
+        assert_eq!(content[1]["type"], "input_audio");
+        assert_eq!(content[1]["input_audio"]["data"], "SUQz");
+        assert_eq!(content[1]["input_audio"]["format"], "mp3");
+    }
+
+    #[test]
+    fn test_text_only_blocks_collapse_to_string() {
+        let messages = vec![json!({
+            "role": "system",
+            "content": [
+                { "type": "text", "text": "You are helpful." },
+                { "type": "text", "text": "Be brief." }
+            ]
+        })];
+
+        let converted = to_openai_messages(messages, &HistoryStyle::default());
+        assert_eq!(converted[0]["content"], "You are helpful.\nBe brief.");
+    }
+
+    #[test]
+    fn test_thinking_blocks_replayed_per_style() {
+        let messages = || {
+            vec![json!({
+                "role": "assistant",
+                "content": [
+                    { "type": "thinking", "thinking": "Need the weather.", "signature": "sig" },
+                    { "type": "text", "text": "Let me check." }
+                ]
+            })]
+        };
+
+        let stripped = to_openai_messages(messages(), &HistoryStyle::default());
+        assert!(stripped[0].get("reasoning_content").is_none());
+        assert_eq!(stripped[0]["content"], "Let me check.");
+
+        let echoed = to_openai_messages(
+            messages(),
+            &HistoryStyle {
+                reasoning: "reasoning_content",
+                ..HistoryStyle::default()
+            },
+        );
+        assert_eq!(echoed[0]["reasoning_content"], "Need the weather.");
+
+        let openrouter = to_openai_messages(
+            messages(),
+            &HistoryStyle {
+                reasoning: "reasoning",
+                ..HistoryStyle::default()
+            },
+        );
+        assert_eq!(openrouter[0]["reasoning"], "Need the weather.");
+        assert!(openrouter[0].get("reasoning_content").is_none());
+    }
+
+    #[test]
+    fn test_assistant_text_kept_with_tool_calls() {
+        let messages = || {
+            vec![json!({
+                "role": "assistant",
+                "content": [
+                    { "type": "text", "text": "First I'll look." },
+                    { "type": "text", "text": "Then I'll answer." },
+                    { "type": "tool_use", "id": "call_1", "name": "search", "input": { "q": "rust" } }
+                ]
+            })]
+        };
+
+        let converted = to_openai_messages(messages(), &HistoryStyle::default());
+        assert_eq!(
+            converted[0]["content"],
+            "First I'll look.\nThen I'll answer."
+        );
+        assert_eq!(converted[0]["tool_calls"][0]["id"], "call_1");
+
+        let strict = to_openai_messages(
+            messages(),
+            &HistoryStyle {
+                content_with_tool_calls: false,
+                ..HistoryStyle::default()
+            },
+        );
+        assert!(strict[0]["content"].is_null());
+        assert_eq!(strict[0]["tool_calls"][0]["function"]["name"], "search");
+    }
+
+    #[test]
+    fn test_each_tool_result_becomes_a_tool_message() {
+        let messages = vec![
+            json!({
+                "role": "tool",
+                "tool_call_id": "call_1",
+                "content": [
+                    { "type": "tool_result", "tool_use_id": "call_1", "content": "sunny" },
+                    {
+                        "type": "tool_result",
+                        "tool_use_id": "call_2",
+                        "content": [
+                            { "type": "text", "text": "line one" },
+                            { "type": "text", "text": "line two" }
+                        ]
+                    },
+                    {
+                        "type": "tool_result",
+                        "tool_use_id": "call_3",
+                        "content": { "status": 404 },
+                        "is_error": true
+                    }
+                ]
+            }),
+            json!({
+                "role": "user",
+                "content": [
+                    { "type": "tool_result", "tool_use_id": "call_4", "content": "done" },
+                    { "type": "text", "text": "Now summarize." }
+                ]
+            }),
+        ];
+
+        let converted = to_openai_messages(messages, &HistoryStyle::default());
+        assert_eq!(converted.len(), 5);
+        assert_eq!(converted[0]["tool_call_id"], "call_1");
+        assert_eq!(converted[0]["content"], "sunny");
+        assert_eq!(converted[1]["tool_call_id"], "call_2");
+        assert_eq!(converted[1]["content"], "line one\nline two");
+        assert_eq!(converted[2]["content"], "Error: {\"status\":404}");
+        assert_eq!(converted[3]["role"], "tool");
+        assert_eq!(converted[3]["tool_call_id"], "call_4");
+        assert_eq!(converted[4]["role"], "user");
+        assert_eq!(converted[4]["content"], "Now summarize.");
+    }
+}
@@ -0,0 +1,142 @@
+//! `/v1/models` listing
+//!
+//! Servers agree on `data[].id` but report limits under their own names:
+//! OpenRouter and Together `context_length` (OpenRouter also
+//! `top_provider.max_completion_tokens`), vLLM `max_model_len`, LM Studio
+//! `max_context_length`, Groq `context_window` and `max_completion_tokens`.
+//! Ollama's native `/api/tags` lists `models[].name` instead. Each entry is
+//! normalised and combined with the model catalog.
+
+use serde_json::Value;
+
+use crate::errors;
+use crate::exports::abk::extension::provider::{ListedModel as WitListedModel, ProviderError};
+use crate::{models, wit_model_info};
+
+/// Fields holding the context window, most specific first
+const CONTEXT_FIELDS: &[&str] = &[
+    "context_length",
+    "max_model_len",
+    "max_context_length",
+    "context_window",
+];
+
+/// Parse a model listing into one record per model, in server order
+pub(crate) fn parse(body: &str) -> Result<Vec<WitListedModel>, ProviderError> {
+    let parse_error = |message: &str| ProviderError {
+        message: message.to_string(),
+        code: Some("PARSE_ERROR".to_string()),
+        http_status: None,
+        response_body: Some(body.to_string()),
+        is_retryable: Some(false),
+        retry_after: None,
+    };
+
+    let json: Value = serde_json::from_str(body)
+        .map_err(|e| parse_error(&format!("Failed to parse models response: {}", e)))?;
+    if errors::is_error_body(&json) {
+        return Err(errors::classify(None, &[], body));
+    }
+
+    let entries = json["data"]
+        .as_array()
+        .or_else(|| json["models"].as_array())
+        .or_else(|| json.as_array())
+        .ok_or_else(|| parse_error("No model list in models response"))?;
+
+    Ok(entries.iter().filter_map(listed_model).collect())
+}
+
+fn listed_model(entry: &Value) -> Option<WitListedModel> {
+    let id = ["id", "model", "name"]
+        .iter()
+        .find_map(|key| entry[*key].as_str())?
+        .to_string();
+
+    let context_window = CONTEXT_FIELDS
+        .iter()
+        .find_map(|key| as_u32(&entry[*key]))
+        .or_else(|| as_u32(&entry["top_provider"]["context_length"]));
+    let max_output_tokens = as_u32(&entry["top_provider"]["max_completion_tokens"])
+        .or_else(|| as_u32(&entry["max_completion_tokens"]));
+
+    // Catalog capabilities, with the limits the server reports
+    let (pattern, mut capabilities) = models::capabilities(&id);
+    if let Some(context_window) = context_window {
+        capabilities.context_window = context_window;
+    }
+    if let Some(max_output_tokens) = max_output_tokens {
+        capabilities.max_output_tokens = max_output_tokens;
+    }
+
+    Some(WitListedModel {
+        name: entry["name"]
+            .as_str()
+            .filter(|name| *name != id)
+            .map(str::to_string),
+        owned_by: entry["owned_by"].as_str().map(str::to_string),
+        created: entry["created"].as_u64(),
+        context_window,
+        max_output_tokens,
+        info: wit_model_info(pattern, capabilities),
+        id,
+    })
+}
+
+fn as_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|n| u32::try_from(n).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_vendor_extensions() {
+        let body = json!({
+            "object": "list",
+            "data": [
+                {
+                    "id": "Qwen/Qwen2.5-Coder-32B-Instruct",
+                    "object": "model",
+                    "owned_by": "vllm",
+                    "created": 1731000000,
+                    "max_model_len": 32768
+                },
+                {
+                    "id": "openai/gpt-4o",
+                    "name": "OpenAI: GPT-4o",
+                    "context_length": 128000,
+                    "top_provider": { "context_length": 128000, "max_completion_tokens": 16384 }
+                },
+                { "id": "gpt-4.1-mini", "object": "model", "owned_by": "system" }
+            ]
+        });
+        let models = parse(&body.to_string()).unwrap();
+
+        assert_eq!(models.len(), 3);
+        assert_eq!(models[0].context_window, Some(32_768));
+        assert_eq!(models[0].info.context_window, 32_768);
+        assert_eq!(models[0].info.pattern, "*");
+        assert_eq!(models[0].owned_by, Some("vllm".to_string()));
+
+        assert_eq!(models[1].name, Some("OpenAI: GPT-4o".to_string()));
+        assert_eq!(models[1].max_output_tokens, Some(16_384));
+        assert!(models[1].info.vision);
+
+        // Nothing reported: catalog limits
+        assert_eq!(models[2].context_window, None);
+        assert_eq!(models[2].info.context_window, 1_047_576);
+    }
+
+    #[test]
+    fn test_parse_ollama_tags() {
+        let body = r#"{"models":[{"name":"llama3.1:8b","model":"llama3.1:8b","size":4920753328}]}"#;
+        let models = parse(body).unwrap();
+        assert_eq!(models[0].id, "llama3.1:8b");
+        assert_eq!(models[0].name, None);
+
+        assert!(parse(r#"{"object":"list"}"#).is_err());
+    }
+}
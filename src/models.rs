@@ -0,0 +1,615 @@
+//! Model capability table
+//!
+//! Maps model-name patterns to what the model accepts so requests can be shaped
+//! per model, and to the limits and features hosts budget against
+//! (`get-model-info`). Names are matched after stripping any vendor prefix
+//! (`openai/o3-mini` on OpenRouter matches `o3-mini`). Hosts can override or
+//! extend the embedded capabilities with `set-model-catalog`.
+
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::exports::abk::extension::provider::ProviderError;
+
+thread_local! {
+    /// Host-supplied capability overrides, pattern → partial capabilities
+    static OVERRIDES: RefCell<Vec<(String, Value)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Limits and features of a model, as reported to hosts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Capabilities {
+    /// Context window in tokens (input and output)
+    pub context_window: u32,
+    /// Maximum output tokens per response
+    pub max_output_tokens: u32,
+    /// Accepts image input
+    pub vision: bool,
+    /// Supports native tool calling
+    pub tools: bool,
+    /// Can return several tool calls in one turn
+    pub parallel_tool_calls: bool,
+    /// Produces reasoning before answering
+    pub reasoning: bool,
+    /// Supports `json_schema` structured output
+    pub json_schema: bool,
+    /// Supports streaming responses
+    pub streaming: bool,
+}
+
+impl Capabilities {
+    /// Full-featured OpenAI chat model
+    const fn openai(context_window: u32, max_output_tokens: u32) -> Self {
+        Self {
+            context_window,
+            max_output_tokens,
+            vision: true,
+            tools: true,
+            parallel_tool_calls: true,
+            reasoning: false,
+            json_schema: true,
+            streaming: true,
+        }
+    }
+
+    /// Conservative defaults for an unknown OpenAI-compatible model
+    const fn unknown() -> Self {
+        Self {
+            vision: false,
+            parallel_tool_calls: false,
+            json_schema: false,
+            ..Self::openai(32_768, 4_096)
+        }
+    }
+
+    const fn text_only(self) -> Self {
+        Self {
+            vision: false,
+            ..self
+        }
+    }
+
+    const fn no_tools(self) -> Self {
+        Self {
+            tools: false,
+            parallel_tool_calls: false,
+            ..self
+        }
+    }
+
+    const fn serial_tools(self) -> Self {
+        Self {
+            parallel_tool_calls: false,
+            ..self
+        }
+    }
+
+    const fn no_json_schema(self) -> Self {
+        Self {
+            json_schema: false,
+            ..self
+        }
+    }
+
+    const fn no_streaming(self) -> Self {
+        Self {
+            streaming: false,
+            ..self
+        }
+    }
+
+    const fn reasoning(self) -> Self {
+        Self {
+            reasoning: true,
+            ..self
+        }
+    }
+}
+
+/// What a model family accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ModelInfo {
+    /// Name pattern: exact name, or a prefix when it ends with `*`
+    pub pattern: &'static str,
+    /// Reasoning model: takes `max_completion_tokens` and rejects sampling
+    /// parameters (temperature, top_p, penalties, logit_bias)
+    pub reasoning: bool,
+    /// Accepts `reasoning_effort`
+    pub reasoning_effort: bool,
+    /// Role used for system prompts: `system`, `developer`, or `user` for
+    /// models that accept neither
+    pub system_role: &'static str,
+    /// Only served by the Responses API (`/v1/responses`)
+    pub responses_api: bool,
+    /// How prior reasoning in assistant history is replayed: `strip`,
+    /// `reasoning_content` or `reasoning`
+    pub reasoning_history: &'static str,
+    /// Emits its reasoning as a leading `<think>` block in the content
+    pub think_tags: bool,
+    /// Limits and features reported to hosts
+    pub capabilities: Capabilities,
+}
+
+impl ModelInfo {
+    const fn chat(pattern: &'static str) -> Self {
+        Self {
+            pattern,
+            reasoning: false,
+            reasoning_effort: false,
+            system_role: "system",
+            responses_api: false,
+            reasoning_history: "strip",
+            think_tags: false,
+            capabilities: Capabilities::unknown(),
+        }
+    }
+
+    const fn reasoning(pattern: &'static str) -> Self {
+        Self {
+            pattern,
+            reasoning: true,
+            reasoning_effort: true,
+            system_role: "developer",
+            responses_api: false,
+            reasoning_history: "strip",
+            think_tags: false,
+            capabilities: Capabilities::unknown(),
+        }
+    }
+
+    /// Reasoning models from the first o1 preview generation
+    const fn early_reasoning(pattern: &'static str) -> Self {
+        Self {
+            pattern,
+            reasoning: true,
+            reasoning_effort: false,
+            system_role: "user",
+            responses_api: false,
+            reasoning_history: "strip",
+            think_tags: false,
+            capabilities: Capabilities::unknown(),
+        }
+    }
+
+    /// Mark a model family as Responses-API only
+    const fn responses_only(self) -> Self {
+        Self {
+            responses_api: true,
+            ..self
+        }
+    }
+
+    /// Set the reported capabilities; OpenAI reasoning models always reason
+    const fn with(self, capabilities: Capabilities) -> Self {
+        Self {
+            capabilities: Capabilities {
+                reasoning: capabilities.reasoning || self.reasoning,
+                ..capabilities
+            },
+            ..self
+        }
+    }
+
+    /// Pass `reasoning_effort` through to a model not known to reason
+    const fn with_reasoning_effort(self) -> Self {
+        Self {
+            reasoning_effort: true,
+            ..self
+        }
+    }
+
+    /// Mark a model family that thinks inline in `<think>` tags
+    const fn with_think_tags(self) -> Self {
+        Self {
+            think_tags: true,
+            ..self
+        }
+    }
+
+    /// Replay prior reasoning to a model that expects it back
+    const fn with_reasoning_history(self, reasoning_history: &'static str) -> Self {
+        Self {
+            reasoning_history,
+            ..self
+        }
+    }
+
+    /// Rewrite a Chat Completions body for this model: token limit field,
+    /// unsupported sampling fields, system role and reasoning effort
+    pub(crate) fn shape_request(&self, body: &mut Value, reasoning_effort: Option<&str>) {
+        if self.reasoning {
+            if let Some(obj) = body.as_object_mut() {
+                if let Some(max_tokens) = obj.remove("max_tokens") {
+                    obj.insert("max_completion_tokens".to_string(), max_tokens);
+                }
+                for field in REASONING_UNSUPPORTED {
+                    obj.remove(*field);
+                }
+            }
+        }
+
+        if self.system_role != "system" {
+            if let Some(messages) = body["messages"].as_array_mut() {
+                for msg in messages.iter_mut().filter(|m| m["role"] == "system") {
+                    msg["role"] = json!(self.system_role);
+                }
+            }
+        }
+
+        match reasoning_effort.filter(|_| self.reasoning_effort) {
+            Some(effort) => body["reasoning_effort"] = json!(effort),
+            None => {
+                if let Some(obj) = body.as_object_mut() {
+                    obj.remove("reasoning_effort");
+                }
+            }
+        }
+    }
+}
+
+/// Known models, most specific pattern first. The final `*` entry is the
+/// default for any other OpenAI-compatible model.
+const MODELS: &[ModelInfo] = &[
+    ModelInfo::early_reasoning("o1-mini*").with(
+        Capabilities::openai(128_000, 65_536)
+            .text_only()
+            .no_tools()
+            .no_json_schema(),
+    ),
+    ModelInfo::early_reasoning("o1-preview*").with(
+        Capabilities::openai(128_000, 32_768)
+            .text_only()
+            .no_tools()
+            .no_json_schema(),
+    ),
+    ModelInfo::reasoning("o1-pro*").responses_only().with(
+        Capabilities::openai(200_000, 100_000)
+            .serial_tools()
+            .no_streaming(),
+    ),
+    ModelInfo::reasoning("o1*").with(Capabilities::openai(200_000, 100_000).serial_tools()),
+    ModelInfo::reasoning("o3-pro*").responses_only().with(
+        Capabilities::openai(200_000, 100_000)
+            .serial_tools()
+            .no_streaming(),
+    ),
+    ModelInfo::reasoning("o3-deep-research*")
+        .responses_only()
+        .with(Capabilities::openai(200_000, 100_000).serial_tools()),
+    ModelInfo::reasoning("o3-mini*").with(
+        Capabilities::openai(200_000, 100_000)
+            .text_only()
+            .serial_tools(),
+    ),
+    ModelInfo::reasoning("o3*").with(Capabilities::openai(200_000, 100_000).serial_tools()),
+    ModelInfo::reasoning("o4-mini-deep-research*")
+        .responses_only()
+        .with(Capabilities::openai(200_000, 100_000).serial_tools()),
+    ModelInfo::reasoning("o4-mini*").with(Capabilities::openai(200_000, 100_000).serial_tools()),
+    ModelInfo::reasoning("codex-mini*")
+        .responses_only()
+        .with(Capabilities::openai(200_000, 100_000).serial_tools()),
+    ModelInfo::chat("computer-use-preview*")
+        .responses_only()
+        .with(Capabilities::openai(8_192, 1_024).no_json_schema()),
+    ModelInfo::chat("gpt-5-chat*").with(Capabilities::openai(128_000, 16_384)),
+    ModelInfo::reasoning("gpt-5-codex*")
+        .responses_only()
+        .with(Capabilities::openai(400_000, 128_000)),
+    ModelInfo::reasoning("gpt-5-pro*")
+        .responses_only()
+        .with(Capabilities::openai(400_000, 272_000)),
+    ModelInfo::reasoning("gpt-5*").with(Capabilities::openai(400_000, 128_000)),
+    ModelInfo::chat("gpt-4.1*").with(Capabilities::openai(1_047_576, 32_768)),
+    ModelInfo::chat("gpt-4o*").with(Capabilities::openai(128_000, 16_384)),
+    ModelInfo::chat("gpt-4-turbo*").with(Capabilities::openai(128_000, 4_096).no_json_schema()),
+    ModelInfo::chat("gpt-4*").with(
+        Capabilities::openai(8_192, 8_192)
+            .text_only()
+            .no_json_schema(),
+    ),
+    ModelInfo::chat("gpt-3.5-turbo*").with(
+        Capabilities::openai(16_385, 4_096)
+            .text_only()
+            .no_json_schema(),
+    ),
+    ModelInfo::chat("deepseek-reasoner*").with(
+        Capabilities::openai(128_000, 64_000)
+            .text_only()
+            .serial_tools()
+            .reasoning(),
+    ),
+    ModelInfo::chat("deepseek-chat*").with(
+        Capabilities::openai(128_000, 8_192)
+            .text_only()
+            .no_json_schema(),
+    ),
+    // Thinking models that require earlier reasoning echoed back on tool turns
+    ModelInfo::chat("kimi-k2-thinking*")
+        .with_reasoning_history("reasoning_content")
+        .with(
+            Capabilities::openai(262_144, 65_536)
+                .text_only()
+                .reasoning(),
+        ),
+    ModelInfo::chat("glm-4.7*")
+        .with_reasoning_history("reasoning_content")
+        .with(
+            Capabilities::openai(200_000, 128_000)
+                .text_only()
+                .reasoning(),
+        ),
+    // Open-weight reasoners that think inline in the content
+    ModelInfo::chat("deepseek-r1*")
+        .with_think_tags()
+        .with(Capabilities::unknown().reasoning()),
+    ModelInfo::chat("qwq*")
+        .with_think_tags()
+        .with(Capabilities::unknown().reasoning()),
+    // Unknown servers may host reasoners (gpt-oss, Qwen3) that take the effort
+    ModelInfo::chat("*").with_reasoning_effort(),
+];
+
+/// Sampling fields reasoning models reject
+const REASONING_UNSUPPORTED: &[&str] = &[
+    "temperature",
+    "top_p",
+    "presence_penalty",
+    "frequency_penalty",
+    "logit_bias",
+    "logprobs",
+    "top_logprobs",
+];
+
+/// Look up the capabilities of `model`
+pub(crate) fn lookup(model: &str) -> &'static ModelInfo {
+    let name = normalized(model);
+    MODELS
+        .iter()
+        .find(|info| matches_pattern(&name, info.pattern))
+        .unwrap_or(&MODELS[MODELS.len() - 1])
+}
+
+/// Capabilities of `model` and the pattern that supplied them: the first
+/// matching host override applied over the embedded entry
+pub(crate) fn capabilities(model: &str) -> (String, Capabilities) {
+    let info = lookup(model);
+    let name = normalized(model);
+
+    let matched = OVERRIDES.with(|overrides| {
+        overrides
+            .borrow()
+            .iter()
+            .find(|(pattern, _)| matches_pattern(&name, pattern))
+            .cloned()
+    });
+    let Some((pattern, fields)) = matched else {
+        return (info.pattern.to_string(), info.capabilities);
+    };
+
+    let mut merged = serde_json::to_value(info.capabilities).unwrap_or_default();
+    if let (Some(merged), Some(fields)) = (merged.as_object_mut(), fields.as_object()) {
+        merged.extend(fields.clone());
+    }
+    let capabilities = serde_json::from_value(merged).unwrap_or(info.capabilities);
+    (pattern, capabilities)
+}
+
+/// Every catalog entry, host overrides first, for provider metadata. A
+/// pattern is listed once, with the capabilities it resolves to.
+pub(crate) fn catalog() -> Vec<Value> {
+    let mut patterns: Vec<String> = OVERRIDES.with(|overrides| {
+        overrides
+            .borrow()
+            .iter()
+            .map(|(pattern, _)| pattern.clone())
+            .collect()
+    });
+    patterns.extend(MODELS.iter().map(|info| info.pattern.to_string()));
+
+    let mut entries = Vec::new();
+    for (i, pattern) in patterns.iter().enumerate() {
+        if !patterns[..i].contains(pattern) {
+            entries.push(entry(pattern, capabilities(pattern).1));
+        }
+    }
+    entries
+}
+
+fn entry(pattern: &str, capabilities: Capabilities) -> Value {
+    let mut entry = json!({ "pattern": pattern });
+    if let (Some(entry), Value::Object(fields)) = (
+        entry.as_object_mut(),
+        serde_json::to_value(capabilities).unwrap_or_default(),
+    ) {
+        entry.extend(fields);
+    }
+    entry
+}
+
+/// Replace the host overrides with `catalog`: a JSON array of entries, each
+/// a `pattern` with (partial) capabilities, most specific pattern first
+pub(crate) fn set_overrides(catalog_json: &str) -> Result<(), ProviderError> {
+    let invalid = |reason: String| ProviderError {
+        message: format!("Invalid model catalog: {}", reason),
+        code: Some("INVALID_CATALOG".to_string()),
+        http_status: None,
+        response_body: Some(catalog_json.to_string()),
+        is_retryable: Some(false),
+        retry_after: None,
+    };
+
+    // An array, since JSON object keys carry no reliable order
+    let catalog: Vec<Value> =
+        serde_json::from_str(catalog_json).map_err(|e| invalid(e.to_string()))?;
+
+    let mut overrides = Vec::new();
+    for (i, entry) in catalog.into_iter().enumerate() {
+        let Value::Object(mut fields) = entry else {
+            return Err(invalid(format!("entry {} must be an object", i)));
+        };
+        let Some(Value::String(pattern)) = fields.remove("pattern") else {
+            return Err(invalid(format!("entry {} needs a string 'pattern'", i)));
+        };
+
+        // Check the field types against a complete entry
+        let mut probe = serde_json::to_value(Capabilities::unknown()).unwrap_or_default();
+        if let Some(probe) = probe.as_object_mut() {
+            probe.extend(fields.clone());
+        }
+        serde_json::from_value::<Capabilities>(probe)
+            .map_err(|e| invalid(format!("'{}': {}", pattern, e)))?;
+        overrides.push((pattern.to_lowercase(), Value::Object(fields)));
+    }
+
+    OVERRIDES.with(|o| *o.borrow_mut() = overrides);
+    Ok(())
+}
+
+/// Model name without vendor prefix, lowercased
+fn normalized(model: &str) -> String {
+    model.rsplit('/').next().unwrap_or(model).to_lowercase()
+}
+
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_body() -> Value {
+        json!({
+            "model": "o3-mini",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "Hi" }
+            ],
+            "temperature": 0.7,
+            "top_p": 0.9,
+            "max_tokens": 500
+        })
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert!(lookup("o3-mini").reasoning);
+        assert!(lookup("openai/o4-mini-2025-04-16").reasoning);
+        assert!(lookup("gpt-5").reasoning);
+        assert!(!lookup("gpt-5-chat-latest").reasoning);
+        assert!(!lookup("gpt-4o-mini").reasoning);
+        assert_eq!(lookup("o1-mini").system_role, "user");
+        assert_eq!(lookup("llama3.1:8b").pattern, "*");
+        assert!(lookup("o3-pro-2025-06-10").responses_api);
+        assert!(!lookup("o3-mini").responses_api);
+        assert_eq!(
+            lookup("moonshotai/kimi-k2-thinking").reasoning_history,
+            "reasoning_content"
+        );
+        assert_eq!(lookup("deepseek-reasoner").reasoning_history, "strip");
+    }
+
+    #[test]
+    fn test_shape_reasoning_request() {
+        let mut body = sample_body();
+        lookup("o3-mini").shape_request(&mut body, Some("high"));
+
+        assert_eq!(body["max_completion_tokens"], 500);
+        assert!(body.get("max_tokens").is_none());
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("top_p").is_none());
+        assert_eq!(body["messages"][0]["role"], "developer");
+        assert_eq!(body["reasoning_effort"], "high");
+    }
+
+    #[test]
+    fn test_shape_chat_request_unchanged() {
+        let mut body = sample_body();
+        lookup("gpt-4o").shape_request(&mut body, None);
+        assert_eq!(body, sample_body());
+
+        // Non-reasoning chat models reject reasoning_effort
+        for model in ["gpt-4o", "gpt-4.1-mini", "gpt-5-chat-latest"] {
+            let mut body = sample_body();
+            body["reasoning_effort"] = json!("high");
+            lookup(model).shape_request(&mut body, Some("low"));
+            assert_eq!(body, sample_body(), "{}", model);
+        }
+        let mut body = sample_body();
+        lookup("llama3.1:8b").shape_request(&mut body, Some("low"));
+        assert_eq!(body["reasoning_effort"], "low");
+
+        // o1-mini accepts neither system/developer messages nor reasoning_effort
+        let mut body = sample_body();
+        lookup("o1-mini").shape_request(&mut body, Some("low"));
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert!(body.get("reasoning_effort").is_none());
+    }
+
+    #[test]
+    fn test_capabilities_and_overrides() {
+        let (pattern, caps) = capabilities("gpt-4o-2024-08-06");
+        assert_eq!(pattern, "gpt-4o*");
+        assert_eq!(caps.context_window, 128_000);
+        assert!(caps.vision && caps.parallel_tool_calls && caps.json_schema);
+
+        assert!(capabilities("o3-mini").1.reasoning);
+        assert!(!capabilities("o3-pro").1.streaming);
+        assert!(!capabilities("o1-mini").1.tools);
+        assert_eq!(capabilities("llama3.1:8b").1, Capabilities::unknown());
+
+        set_overrides(
+            r#"[{"pattern":"llama3.1*","context_window":131072,"vision":false},
+                {"pattern":"gpt-4o*","max_output_tokens":4096}]"#,
+        )
+        .unwrap();
+        let (pattern, caps) = capabilities("llama3.1:8b");
+        assert_eq!(pattern, "llama3.1*");
+        assert_eq!(caps.context_window, 131_072);
+        assert_eq!(caps.max_output_tokens, 4_096);
+        assert_eq!(capabilities("gpt-4o").1.context_window, 128_000);
+        assert_eq!(capabilities("gpt-4o").1.max_output_tokens, 4_096);
+
+        // Overriding a built-in pattern replaces its catalog entry
+        let listed: Vec<Value> = catalog()
+            .into_iter()
+            .filter(|e| e["pattern"] == "gpt-4o*")
+            .collect();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["max_output_tokens"], 4_096);
+
+        for bad in [
+            r#"[{"pattern":"x*","vision":"yes"}]"#,
+            r#"[{"vision":true}]"#,
+            r#"{"x*":{"vision":true}}"#,
+        ] {
+            let err = set_overrides(bad).unwrap_err();
+            assert_eq!(err.code, Some("INVALID_CATALOG".to_string()));
+        }
+        set_overrides("[]").unwrap();
+        assert_eq!(capabilities("llama3.1:8b").0, "*");
+    }
+
+    #[test]
+    fn test_overrides_keep_given_order() {
+        // Alphabetically "llama3*" sorts before "llama3.1-405b*"; the given order wins
+        set_overrides(
+            r#"[{"pattern":"llama3.1-405b*","context_window":128000},
+                {"pattern":"llama3*","context_window":8192}]"#,
+        )
+        .unwrap();
+        assert_eq!(capabilities("llama3.1-405b-instruct").0, "llama3.1-405b*");
+        assert_eq!(
+            capabilities("llama3.1-405b-instruct").1.context_window,
+            128_000
+        );
+        assert_eq!(capabilities("llama3-8b").1.context_window, 8_192);
+        set_overrides("[]").unwrap();
+    }
+}
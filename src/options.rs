@@ -0,0 +1,457 @@
+//! Request options passed as `options-json`
+//!
+//! A JSON object of optional settings accepted by `format-request-with-options`
+//! and `parse-response-with-options`. Hosts pass the same object to both so the
+//! response can be checked against what was requested. Unknown keys are ignored.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::exports::abk::extension::provider::ProviderError;
+use crate::messages::HistoryStyle;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct RequestOptions {
+    /// OpenAI `response_format`: `{"type":"json_object"}` or
+    /// `{"type":"json_schema","json_schema":{"name","schema","strict"}}`
+    pub response_format: Option<Value>,
+
+    // ===== Sampling parameters =====
+    /// Nucleus sampling probability mass (0.0-1.0)
+    pub top_p: Option<f64>,
+    /// Stop sequence or list of up to 4 stop sequences
+    pub stop: Option<Value>,
+    /// Seed for best-effort deterministic sampling
+    pub seed: Option<i64>,
+    /// Penalty for tokens already present (-2.0-2.0)
+    pub presence_penalty: Option<f64>,
+    /// Penalty proportional to token frequency (-2.0-2.0)
+    pub frequency_penalty: Option<f64>,
+    /// Token id → bias (-100-100)
+    pub logit_bias: Option<BTreeMap<String, f64>>,
+    /// Number of choices to generate (only the first is parsed)
+    pub n: Option<u32>,
+
+    /// Reasoning effort for reasoning models (none, minimal, low, medium, high)
+    pub reasoning_effort: Option<String>,
+    /// How thinking blocks in assistant history are sent: `strip`, or echoed
+    /// as `reasoning_content` (DeepSeek/GLM/Kimi) or `reasoning` (OpenRouter)
+    pub reasoning_history: Option<String>,
+    /// Send assistant text alongside `tool_calls`; defaults to what the
+    /// selected backend accepts (off for Mistral and Ollama)
+    pub content_with_tool_calls: Option<bool>,
+    /// Tool calling: `native` (default), `prompt` for backends that ignore
+    /// `tools`, or `functions` for servers that only know the legacy
+    /// `functions` / `function_call` fields; prompt mode describes the tools
+    /// in the system prompt and parses calls out of the reply text
+    pub tool_mode: Option<String>,
+    /// Names of the tools sent with the request; in prompt mode only calls
+    /// to these are parsed, and fenced JSON only when this is given
+    pub tool_names: Vec<String>,
+    /// Move a leading `<think>...</think>` block to the reasoning (default
+    /// per model: on for DeepSeek-R1 distills and QwQ)
+    pub think_tags: Option<bool>,
+    /// The chat template opens the `<think>` block, so the reply starts as
+    /// reasoning up to the first `</think>` (implies `think_tags`)
+    pub think_opened: Option<bool>,
+    /// Conversation check before sending: `off` (default), `strict` or `repair`
+    pub validate_history: Option<String>,
+    /// Trim history to the context window: `off` (default), `drop` or
+    /// `summarize`
+    pub fit_to_context: Option<String>,
+    /// Share of the context budget `fit_to_context` leaves free for error in
+    /// the token estimate (default 0.1)
+    pub fit_margin: Option<f64>,
+
+    // ===== Responses API =====
+    /// API to use: `chat` (Chat Completions) or `responses`; defaults to
+    /// `responses` only for models served exclusively there
+    pub api: Option<String>,
+    /// Continue a stored Responses API conversation
+    pub previous_response_id: Option<String>,
+    /// Whether the Responses API stores the response server-side
+    pub store: Option<bool>,
+    /// Responses API built-in tools, e.g. `{"type":"web_search"}`
+    pub builtin_tools: Vec<Value>,
+
+    // ===== Backend =====
+    /// Backend profile: openai, mistral, groq, ollama or gemini (detected
+    /// from `base_url` or the model name otherwise)
+    pub backend: Option<String>,
+    /// Base URL of the server, for backend detection in request formatting
+    pub base_url: Option<String>,
+
+    // ===== Azure OpenAI =====
+    /// Force Azure mode on or off (detected from the base URL host otherwise)
+    pub azure: Option<bool>,
+    /// Azure data-plane `api-version`
+    pub azure_api_version: Option<String>,
+    /// Model name → Azure deployment name
+    pub azure_deployments: BTreeMap<String, String>,
+
+    // ===== Headers =====
+    /// Sent as `OpenAI-Organization`
+    pub organization: Option<String>,
+    /// Sent as `OpenAI-Project`
+    pub project: Option<String>,
+    /// Application URL, sent to OpenRouter as `HTTP-Referer`
+    pub app_url: Option<String>,
+    /// Application name, sent to OpenRouter as `X-Title`
+    pub app_name: Option<String>,
+    /// Additional headers; GitHub Copilot headers are rejected
+    pub extra_headers: BTreeMap<String, String>,
+}
+
+impl RequestOptions {
+    /// Parse `options-json`; `None` or an empty string yields the defaults
+    pub(crate) fn parse(options_json: Option<&str>) -> Result<Self, ProviderError> {
+        let Some(json) = options_json.filter(|s| !s.trim().is_empty()) else {
+            return Ok(Self::default());
+        };
+
+        let options: Self = serde_json::from_str(json).map_err(|e| ProviderError {
+            message: format!("Failed to parse options JSON: {}", e),
+            code: Some("INVALID_OPTIONS".to_string()),
+            http_status: None,
+            response_body: Some(json.to_string()),
+            is_retryable: Some(false),
+            retry_after: None,
+        })?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    fn validate(&self) -> Result<(), ProviderError> {
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        check_range("n", self.n.map(f64::from), 1.0, 128.0)?;
+        check_range("fit_margin", self.fit_margin, 0.0, 0.5)?;
+
+        if let Some(stop) = &self.stop {
+            let sequences: Vec<&Value> = match stop {
+                Value::Array(items) => items.iter().collect(),
+                other => vec![other],
+            };
+            if sequences.len() > 4 {
+                return Err(invalid_parameter(
+                    "stop",
+                    "at most 4 stop sequences are allowed",
+                ));
+            }
+            if !sequences
+                .iter()
+                .all(|s| s.as_str().is_some_and(|s| !s.is_empty()))
+            {
+                return Err(invalid_parameter(
+                    "stop",
+                    "stop sequences must be non-empty strings",
+                ));
+            }
+        }
+
+        if let Some(api) = &self.api {
+            if api != "chat" && api != "responses" {
+                return Err(invalid_parameter("api", "must be chat or responses"));
+            }
+        }
+
+        if let Some(effort) = &self.reasoning_effort {
+            if !["none", "minimal", "low", "medium", "high"].contains(&effort.as_str()) {
+                return Err(invalid_parameter(
+                    "reasoning_effort",
+                    "must be none, minimal, low, medium or high",
+                ));
+            }
+        }
+
+        if let Some(mode) = &self.reasoning_history {
+            if !["strip", "reasoning_content", "reasoning"].contains(&mode.as_str()) {
+                return Err(invalid_parameter(
+                    "reasoning_history",
+                    "must be strip, reasoning_content or reasoning",
+                ));
+            }
+        }
+
+        if let Some(backend) = &self.backend {
+            if crate::backends::by_name(backend).is_none() {
+                return Err(invalid_parameter(
+                    "backend",
+                    "must be openai, mistral, groq, ollama or gemini",
+                ));
+            }
+        }
+
+        if let Some(mode) = &self.tool_mode {
+            if !["native", "prompt", "functions"].contains(&mode.as_str()) {
+                return Err(invalid_parameter(
+                    "tool_mode",
+                    "must be native, prompt or functions",
+                ));
+            }
+        }
+
+        if let Some(mode) = &self.validate_history {
+            if !["off", "strict", "repair"].contains(&mode.as_str()) {
+                return Err(invalid_parameter(
+                    "validate_history",
+                    "must be off, strict or repair",
+                ));
+            }
+        }
+
+        if let Some(mode) = &self.fit_to_context {
+            if !["off", "drop", "summarize"].contains(&mode.as_str()) {
+                return Err(invalid_parameter(
+                    "fit_to_context",
+                    "must be off, drop or summarize",
+                ));
+            }
+        }
+
+        if let Some(name) = self
+            .extra_headers
+            .keys()
+            .find(|name| crate::endpoint::is_forbidden_header(name))
+        {
+            return Err(invalid_parameter(
+                "extra_headers",
+                &format!("'{}' is a GitHub Copilot header and is never sent", name),
+            ));
+        }
+
+        if let Some(bias) = &self.logit_bias {
+            for (token, value) in bias {
+                if token.parse::<u32>().is_err() {
+                    return Err(invalid_parameter(
+                        "logit_bias",
+                        &format!("key '{}' is not a token id", token),
+                    ));
+                }
+                check_range("logit_bias", Some(*value), -100.0, 100.0)?;
+            }
+        }
+
+        if let Some(format) = &self.response_format {
+            match format["type"].as_str() {
+                Some("text") | Some("json_object") => {}
+                Some("json_schema") if format["json_schema"]["schema"].is_object() => {}
+                Some("json_schema") => {
+                    return Err(invalid_parameter(
+                        "response_format",
+                        "json_schema requires a json_schema.schema object",
+                    ))
+                }
+                _ => {
+                    return Err(invalid_parameter(
+                        "response_format",
+                        "type must be text, json_object or json_schema",
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the sampling parameters that were set onto the request body
+    pub(crate) fn apply_sampling(&self, body: &mut Value) {
+        if let Some(top_p) = self.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(stop) = &self.stop {
+            body["stop"] = stop.clone();
+        }
+        if let Some(seed) = self.seed {
+            body["seed"] = json!(seed);
+        }
+        if let Some(penalty) = self.presence_penalty {
+            body["presence_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = self.frequency_penalty {
+            body["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(bias) = &self.logit_bias {
+            body["logit_bias"] = json!(bias);
+        }
+        if let Some(n) = self.n {
+            body["n"] = json!(n);
+        }
+    }
+
+    /// Whether a leading `<think>` block is split out of `model`'s content
+    pub(crate) fn think_tags(&self, model: &str) -> bool {
+        self.think_opened()
+            || self
+                .think_tags
+                .unwrap_or_else(|| crate::models::lookup(model).think_tags)
+    }
+
+    /// Whether the reply starts inside a template-opened `<think>` block
+    pub(crate) fn think_opened(&self) -> bool {
+        self.think_opened == Some(true)
+    }
+
+    /// Whether tool calls are emulated through the prompt
+    pub(crate) fn prompt_tools(&self) -> bool {
+        self.tool_mode.as_deref() == Some("prompt")
+    }
+
+    /// Whether tools are sent with the legacy `functions` protocol
+    pub(crate) fn legacy_functions(&self) -> bool {
+        self.tool_mode.as_deref() == Some("functions")
+    }
+
+    /// Whether requests for `model` go to the Responses API
+    pub(crate) fn uses_responses_api(&self, model: &str) -> bool {
+        match self.api.as_deref() {
+            Some(api) => api == "responses",
+            None => crate::models::lookup(model).responses_api,
+        }
+    }
+
+    /// How assistant history for `model` carries earlier reasoning
+    pub(crate) fn history_style(&self, model: &str) -> HistoryStyle {
+        let info = crate::models::lookup(model);
+        HistoryStyle {
+            reasoning: match self.reasoning_history.as_deref() {
+                Some("reasoning_content") => "reasoning_content",
+                Some("reasoning") => "reasoning",
+                Some(_) => "strip",
+                None => info.reasoning_history,
+            },
+            content_with_tool_calls: self
+                .content_with_tool_calls
+                .unwrap_or_else(|| crate::backends::select(model, self).content_with_tool_calls()),
+        }
+    }
+
+    /// JSON schema the response content must satisfy, if any
+    pub(crate) fn response_schema(&self) -> Option<&Value> {
+        let format = self.response_format.as_ref()?;
+        (format["type"] == "json_schema").then(|| &format["json_schema"]["schema"])
+    }
+
+    /// Whether the response content must be JSON
+    pub(crate) fn expects_json(&self) -> bool {
+        self.response_format.as_ref().is_some_and(|f| {
+            matches!(
+                f["type"].as_str(),
+                Some("json_object") | Some("json_schema")
+            )
+        })
+    }
+}
+
+/// Error for an option that is present but not acceptable
+pub(crate) fn invalid_parameter(name: &str, reason: &str) -> ProviderError {
+    ProviderError {
+        message: format!("Invalid parameter '{}': {}", name, reason),
+        code: Some("INVALID_PARAMETER".to_string()),
+        http_status: None,
+        response_body: None,
+        is_retryable: Some(false),
+        retry_after: None,
+    }
+}
+
+pub(crate) fn check_range(
+    name: &str,
+    value: Option<f64>,
+    min: f64,
+    max: f64,
+) -> Result<(), ProviderError> {
+    match value {
+        Some(v) if !(min..=max).contains(&v) => Err(invalid_parameter(
+            name,
+            &format!("{} is outside the range {} to {}", v, min, max),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_parameters_applied() {
+        let options = RequestOptions::parse(Some(
+            r#"{"top_p":0.9,"stop":["```","END"],"seed":42,"presence_penalty":0.5,
+                "frequency_penalty":-0.5,"logit_bias":{"50256":-100},"n":2}"#,
+        ))
+        .unwrap();
+        let mut body = json!({});
+        options.apply_sampling(&mut body);
+
+        assert_eq!(body["top_p"], 0.9);
+        assert_eq!(body["stop"], json!(["```", "END"]));
+        assert_eq!(body["seed"], 42);
+        assert_eq!(body["presence_penalty"], 0.5);
+        assert_eq!(body["frequency_penalty"], -0.5);
+        assert_eq!(body["logit_bias"]["50256"], -100.0);
+        assert_eq!(body["n"], 2);
+    }
+
+    #[test]
+    fn test_out_of_range_parameters_rejected() {
+        for options in [
+            r#"{"top_p":1.5}"#,
+            r#"{"presence_penalty":-3}"#,
+            r#"{"frequency_penalty":2.5}"#,
+            r#"{"n":0}"#,
+            r#"{"stop":["a","b","c","d","e"]}"#,
+            r#"{"stop":""}"#,
+            r#"{"logit_bias":{"50256":101}}"#,
+            r#"{"logit_bias":{"hello":1}}"#,
+            r#"{"reasoning_effort":"max"}"#,
+            r#"{"api":"completions"}"#,
+            r#"{"reasoning_history":"echo"}"#,
+            r#"{"validate_history":"fix"}"#,
+            r#"{"tool_mode":"json"}"#,
+            r#"{"fit_to_context":"truncate"}"#,
+            r#"{"backend":"anthropic"}"#,
+            r#"{"extra_headers":{"Copilot-Integration-Id":"vscode-chat"}}"#,
+        ] {
+            let err = RequestOptions::parse(Some(options)).unwrap_err();
+            assert_eq!(
+                err.code,
+                Some("INVALID_PARAMETER".to_string()),
+                "{}",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn test_unparseable_options() {
+        let err = RequestOptions::parse(Some(r#"{"seed":"abc"}"#)).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_OPTIONS".to_string()));
+        assert!(RequestOptions::parse(Some("")).is_ok());
+    }
+
+    #[test]
+    fn test_content_with_tool_calls_per_backend() {
+        let none = RequestOptions::default();
+        assert!(none.history_style("gpt-4o").content_with_tool_calls);
+        assert!(
+            !none
+                .history_style("mistral-large-latest")
+                .content_with_tool_calls
+        );
+
+        let ollama =
+            RequestOptions::parse(Some(r#"{"base_url":"http://localhost:11434/v1"}"#)).unwrap();
+        assert!(!ollama.history_style("llama3.1").content_with_tool_calls);
+
+        let forced = RequestOptions::parse(Some(r#"{"content_with_tool_calls":true}"#)).unwrap();
+        assert!(
+            forced
+                .history_style("mistral-large-latest")
+                .content_with_tool_calls
+        );
+    }
+}
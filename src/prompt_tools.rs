@@ -0,0 +1,442 @@
+//! Prompt-based tool calling for backends without native function calling
+//!
+//! With `tool_mode: "prompt"` the tool schemas and a call protocol go into the
+//! system prompt instead of `tools`, and earlier tool turns are replayed as
+//! text. Calls come back inside the content, either Hermes/Qwen style
+//! (`<tool_call>{"name":...,"arguments":{...}}</tool_call>`) or as fenced JSON,
+//! and are turned into regular tool calls with generated ids. A fenced block
+//! may just as well be a JSON answer, so it only counts as a call when it
+//! names one of the declared tools and carries `arguments`/`parameters`.
+
+use serde_json::{json, Value};
+
+use crate::tags::{Piece, TagSplitter};
+use crate::{ids, Choice, FunctionCall, OpenAIToolCall};
+
+pub(crate) const OPEN_TAG: &str = "<tool_call>";
+pub(crate) const CLOSE_TAG: &str = "</tool_call>";
+
+// ===== Requests =====
+
+/// Move `tools`/`tool_choice` into the system prompt and rewrite tool turns
+/// in the history as text
+pub(crate) fn apply(body: &mut Value) {
+    let Some(obj) = body.as_object_mut() else {
+        return;
+    };
+    let tools = obj
+        .remove("tools")
+        .and_then(|t| t.as_array().cloned())
+        .unwrap_or_default();
+    let choice = obj.remove("tool_choice");
+    obj.remove("parallel_tool_calls");
+
+    let history = body["messages"].as_array().cloned().unwrap_or_default();
+    let mut messages = replay_history(history);
+
+    if !tools.is_empty() && choice.as_ref().is_none_or(|c| c != "none") {
+        let prompt = system_prompt(&tools, choice.as_ref());
+        match messages.first_mut().filter(|m| m["role"] == "system") {
+            Some(system) => {
+                let existing = system["content"].as_str().unwrap_or("").to_string();
+                system["content"] = json!(format!("{}\n\n{}", existing, prompt));
+            }
+            None => messages.insert(0, json!({ "role": "system", "content": prompt })),
+        }
+    }
+
+    body["messages"] = json!(messages);
+}
+
+/// Tool descriptions and the call protocol
+fn system_prompt(tools: &[Value], choice: Option<&Value>) -> String {
+    let schemas: Vec<String> = tools
+        .iter()
+        .map(|tool| {
+            let function = tool.get("function").unwrap_or(tool);
+            json!({
+                "name": function["name"],
+                "description": function["description"],
+                "parameters": function["parameters"],
+            })
+            .to_string()
+        })
+        .collect();
+
+    let mut prompt = format!(
+        "# Tools\n\nYou may call the following tools, described by JSON schemas:\n\n<tools>\n{}\n</tools>\n\n\
+         To call a tool, reply with one block per call:\n{}\n{{\"name\": \"<tool name>\", \"arguments\": {{<arguments>}}}}\n{}\n\
+         Then stop; results are returned in <tool_response> blocks.",
+        schemas.join("\n"),
+        OPEN_TAG,
+        CLOSE_TAG
+    );
+
+    if let Some(name) = choice.and_then(|c| c["function"]["name"].as_str()) {
+        prompt.push_str(&format!("\nYou must call the tool \"{}\".", name));
+    } else if choice.is_some_and(|c| c == "required") {
+        prompt.push_str("\nYou must call at least one tool.");
+    }
+    prompt
+}
+
+/// Assistant tool calls become `<tool_call>` text and tool results become
+/// user `<tool_response>` messages (one per run of results)
+fn replay_history(messages: Vec<Value>) -> Vec<Value> {
+    let mut out: Vec<Value> = Vec::new();
+    let mut previous_tool = false;
+
+    for msg in messages {
+        let is_tool = msg["role"] == "tool";
+        match msg["role"].as_str() {
+            Some("assistant") if msg["tool_calls"].is_array() => {
+                let mut text: Vec<String> = msg["content"]
+                    .as_str()
+                    .map(|t| vec![t.to_string()])
+                    .unwrap_or_default();
+                for call in msg["tool_calls"].as_array().into_iter().flatten() {
+                    let arguments = call["function"]["arguments"]
+                        .as_str()
+                        .and_then(|a| serde_json::from_str::<Value>(a).ok())
+                        .unwrap_or_else(|| json!({}));
+                    text.push(format!(
+                        "{}\n{}\n{}",
+                        OPEN_TAG,
+                        json!({ "name": call["function"]["name"], "arguments": arguments }),
+                        CLOSE_TAG
+                    ));
+                }
+                let mut replayed = msg.clone();
+                if let Some(obj) = replayed.as_object_mut() {
+                    obj.remove("tool_calls");
+                }
+                replayed["content"] = json!(text.join("\n"));
+                out.push(replayed);
+            }
+            Some("tool") => {
+                let response = format!(
+                    "<tool_response>\n{}\n</tool_response>",
+                    msg["content"].as_str().unwrap_or("")
+                );
+                match out.last_mut().filter(|_| previous_tool) {
+                    Some(last) => {
+                        let existing = last["content"].as_str().unwrap_or("").to_string();
+                        last["content"] = json!(format!("{}\n{}", existing, response));
+                    }
+                    None => out.push(json!({ "role": "user", "content": response })),
+                }
+            }
+            _ => out.push(msg),
+        }
+        previous_tool = is_tool;
+    }
+    out
+}
+
+// ===== Responses =====
+
+/// Pull emulated calls to `tool_names` out of the choice's content and mark
+/// the choice as a tool-call turn when it has any. Content is trimmed only
+/// when a call came out of it (or `split_earlier`, for streams that already
+/// removed their tagged calls).
+pub(crate) fn extract_into(choice: &mut Choice, tool_names: &[String], split_earlier: bool) {
+    let existing = choice.message.tool_calls.take().unwrap_or_default();
+
+    let (rest, found) = match choice.message.content.as_deref() {
+        Some(text) => extract_calls(text, existing.len(), tool_names),
+        None => (String::new(), Vec::new()),
+    };
+
+    // Content without a call is left exactly as the model wrote it
+    if !found.is_empty() || split_earlier {
+        let rest = rest.trim();
+        choice.message.content = (!rest.is_empty()).then(|| rest.to_string());
+    }
+
+    let mut calls = existing;
+    calls.extend(found);
+    if !calls.is_empty() {
+        if matches!(choice.finish_reason.as_deref(), None | Some("stop")) {
+            choice.finish_reason = Some("tool_calls".to_string());
+        }
+        choice.message.tool_calls = Some(calls);
+    }
+}
+
+/// Split `text` into the remaining prose and the tool calls it contains;
+/// generated ids continue from `first_index`
+fn extract_calls(
+    text: &str,
+    first_index: usize,
+    tool_names: &[String],
+) -> (String, Vec<OpenAIToolCall>) {
+    let mut rest = String::new();
+    let mut calls = Vec::new();
+
+    let mut splitter = TagSplitter::new(OPEN_TAG, CLOSE_TAG);
+    let mut pieces = splitter.push(text);
+    pieces.extend(splitter.flush());
+    if splitter.inside() {
+        // An unclosed final block still counts as a call if it parses
+        pieces.push(Piece::Close);
+    }
+
+    let mut block = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Outside(text) => rest.push_str(&text),
+            Piece::Inside(text) => block.push_str(&text),
+            Piece::Close => {
+                let parsed = parse_calls(&block, first_index + calls.len(), tool_names);
+                if parsed.is_empty() {
+                    rest.push_str(&format!("{}{}{}", OPEN_TAG, block, CLOSE_TAG));
+                }
+                calls.extend(parsed);
+                block.clear();
+            }
+        }
+    }
+
+    let (rest, fenced) = extract_fenced(&rest, first_index + calls.len(), tool_names);
+    calls.extend(fenced);
+    (rest, calls)
+}
+
+/// Fenced code blocks whose JSON is a call (or a list of calls) to a
+/// declared tool, with its arguments
+fn extract_fenced(
+    text: &str,
+    first_index: usize,
+    tool_names: &[String],
+) -> (String, Vec<OpenAIToolCall>) {
+    if tool_names.is_empty() {
+        return (text.to_string(), Vec::new());
+    }
+    let mut rest = String::new();
+    let mut calls = Vec::new();
+    let mut remaining = text;
+
+    while let Some(start) = remaining.find("```") {
+        let after_fence = &remaining[start + 3..];
+        let Some(end) = after_fence.find("```") else {
+            break;
+        };
+        // Skip the language tag on the opening fence line
+        let inner = match after_fence[..end].split_once('\n') {
+            Some((lang, body)) if !lang.trim_start().starts_with(['{', '[']) => body,
+            _ => &after_fence[..end],
+        };
+
+        let parsed = if has_arguments(inner) {
+            parse_calls(inner, first_index + calls.len(), tool_names)
+        } else {
+            Vec::new()
+        };
+        if parsed.is_empty() {
+            rest.push_str(&remaining[..start + 3 + end + 3]);
+        } else {
+            rest.push_str(&remaining[..start]);
+            calls.extend(parsed);
+        }
+        remaining = &after_fence[end + 3..];
+    }
+    rest.push_str(remaining);
+    (rest, calls)
+}
+
+/// Whether every call in a JSON object or array carries `arguments` or
+/// `parameters`
+fn has_arguments(text: &str) -> bool {
+    let Ok(value) = serde_json::from_str::<Value>(text.trim()) else {
+        return false;
+    };
+    let items = match value {
+        Value::Array(items) => items,
+        other => vec![other],
+    };
+    !items.is_empty()
+        && items.iter().all(|item| {
+            let call = item
+                .get("function")
+                .filter(|f| f.is_object())
+                .unwrap_or(item);
+            call.get("arguments").is_some() || call.get("parameters").is_some()
+        })
+}
+
+/// Calls described by a JSON object or array; empty when `text` is not one,
+/// or names a tool outside `tool_names` (any name when none are declared)
+pub(crate) fn parse_calls(
+    text: &str,
+    first_index: usize,
+    tool_names: &[String],
+) -> Vec<OpenAIToolCall> {
+    let Ok(value) = serde_json::from_str::<Value>(text.trim()) else {
+        return Vec::new();
+    };
+    let items = match value {
+        Value::Array(items) => items,
+        other => vec![other],
+    };
+
+    let mut calls = Vec::new();
+    for item in &items {
+        let call = item
+            .get("function")
+            .filter(|f| f.is_object())
+            .unwrap_or(item);
+        let Some(name) = call["name"].as_str() else {
+            return Vec::new();
+        };
+        if !tool_names.is_empty() && !tool_names.iter().any(|t| t == name) {
+            return Vec::new();
+        }
+        let arguments = match call.get("arguments").or_else(|| call.get("parameters")) {
+            Some(Value::String(s)) => s.clone(),
+            Some(args) => args.to_string(),
+            None => "{}".to_string(),
+        };
+        calls.push(OpenAIToolCall {
+            id: generated_id(name, &arguments, first_index + calls.len()),
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments,
+            },
+        });
+    }
+    calls
+}
+
+/// Stable id for an emulated call, so streaming and parsing agree
+fn generated_id(name: &str, arguments: &str, index: usize) -> String {
+    ids::call_id(&[name, arguments, &index.to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseMessage;
+
+    fn choice(content: &str) -> Choice {
+        Choice {
+            message: ResponseMessage {
+                role: "assistant".to_string(),
+                content: Some(content.to_string()),
+                ..ResponseMessage::default()
+            },
+            finish_reason: Some("stop".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_request_moves_tools_into_system_prompt() {
+        let mut body = json!({
+            "model": "llama3",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "Weather?" },
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_1", "content": "sunny" }
+            ],
+            "tools": [{ "type": "function", "function": {
+                "name": "get_weather", "description": "Weather", "parameters": { "type": "object" }
+            }}],
+            "tool_choice": "required"
+        });
+        apply(&mut body);
+
+        assert!(body.get("tools").is_none());
+        assert!(body.get("tool_choice").is_none());
+        let system = body["messages"][0]["content"].as_str().unwrap();
+        assert!(system.starts_with("Be brief.\n\n# Tools"));
+        assert!(system.contains("\"name\":\"get_weather\""));
+        assert!(system.contains("You must call at least one tool."));
+
+        let assistant = &body["messages"][2];
+        assert!(assistant.get("tool_calls").is_none());
+        assert!(assistant["content"]
+            .as_str()
+            .unwrap()
+            .contains(r#"{"arguments":{"city":"Paris"},"name":"get_weather"}"#));
+        assert_eq!(
+            body["messages"][3],
+            json!({ "role": "user", "content": "<tool_response>\nsunny\n</tool_response>" })
+        );
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_extract_tagged_and_fenced_calls() {
+        let tools = names(&["get_weather", "search"]);
+        let mut tagged = choice(
+            "Let me check.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>",
+        );
+        extract_into(&mut tagged, &tools, false);
+        let calls = tagged.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tagged.message.content, Some("Let me check.".to_string()));
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+        assert!(calls[0].id.starts_with("call_"));
+        assert_eq!(tagged.finish_reason, Some("tool_calls".to_string()));
+
+        let mut fenced =
+            choice("```json\n{\"name\": \"search\", \"parameters\": {\"q\": \"rust\"}}\n```");
+        extract_into(&mut fenced, &tools, false);
+        assert_eq!(fenced.message.content, None);
+        assert_eq!(
+            fenced.message.tool_calls.unwrap()[0].function.name,
+            "search"
+        );
+
+        // Ordinary code stays content
+        let mut code = choice("Example:\n```json\n{\"a\": 1}\n```");
+        extract_into(&mut code, &tools, false);
+        assert!(code.message.tool_calls.is_none());
+        assert_eq!(code.finish_reason, Some("stop".to_string()));
+
+        // Undeclared tools in tagged blocks stay content
+        let mut unknown = choice("<tool_call>{\"name\": \"rm\", \"arguments\": {}}</tool_call>");
+        extract_into(&mut unknown, &tools, false);
+        assert!(unknown.message.tool_calls.is_none());
+    }
+
+    #[test]
+    fn test_fenced_json_answer_stays_content() {
+        let answer = "Here is the record:\n```json\n{\"name\": \"Ada\", \"age\": 3}\n```";
+
+        // A declared name without arguments is still an answer
+        for tools in [names(&["get_weather"]), names(&["Ada"]), Vec::new()] {
+            let mut reply = choice(answer);
+            extract_into(&mut reply, &tools, false);
+            assert_eq!(reply.message.content, Some(answer.to_string()));
+            assert!(reply.message.tool_calls.is_none());
+        }
+
+        // Arguments but no declared tool of that name
+        let call = "```json\n{\"name\": \"Ada\", \"arguments\": {}}\n```";
+        let mut reply = choice(call);
+        extract_into(&mut reply, &names(&["get_weather"]), false);
+        assert_eq!(reply.message.content, Some(call.to_string()));
+    }
+
+    #[test]
+    fn test_content_without_calls_untouched() {
+        for text in [
+            "\n  Indented answer.\n\n",
+            "Write <tool_call>like this",
+            "   ",
+        ] {
+            let mut reply = choice(text);
+            extract_into(&mut reply, &names(&["get_weather"]), false);
+            assert_eq!(reply.message.content, Some(text.to_string()));
+            assert_eq!(reply.finish_reason, Some("stop".to_string()));
+        }
+    }
+}
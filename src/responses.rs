@@ -0,0 +1,630 @@
+//! OpenAI Responses API (`/v1/responses`)
+//!
+//! Requests are built as Chat Completions bodies first (so message conversion
+//! and model shaping stay in one place) and then rewritten into Responses
+//! form. Responses, including streamed `response.*` events, are mapped back
+//! onto the chat types so both APIs produce the same assistant message.
+
+use serde_json::{json, Map, Value};
+
+use crate::endpoint;
+use crate::exports::abk::extension::provider::{
+    ContentDelta as WitContentDelta, ProviderError, ToolCall as WitToolCall,
+};
+use crate::options::{invalid_parameter, RequestOptions};
+use crate::stream::empty_delta;
+use crate::{
+    errors, wit_usage, Choice, CompletionTokensDetails, FunctionCall, OpenAIToolCall, OpenAIUsage,
+    PromptTokensDetails, ResponseMessage,
+};
+
+// ===== Requests =====
+
+/// Chat Completions fields with no Responses API equivalent
+const UNSUPPORTED_FIELDS: &[&str] = &[
+    "stop",
+    "seed",
+    "presence_penalty",
+    "frequency_penalty",
+    "logit_bias",
+    "n",
+];
+
+/// Rewrite a shaped Chat Completions body into a Responses API body. Fields
+/// and history the Responses API cannot take are rejected rather than
+/// silently dropped.
+pub(crate) fn from_chat_body(
+    chat: &Value,
+    options: &RequestOptions,
+) -> Result<Value, ProviderError> {
+    if let Some(field) = UNSUPPORTED_FIELDS.iter().find(|f| chat.get(**f).is_some()) {
+        return Err(invalid_parameter(
+            field,
+            "not supported by the Responses API",
+        ));
+    }
+
+    // The Azure v1 endpoint is not deployment-scoped: the model names the deployment
+    let mut model = chat["model"].clone();
+    if let Some(name) = model.as_str() {
+        if endpoint::is_azure(options.base_url.as_deref().unwrap_or(""), options) {
+            model = json!(endpoint::azure_deployment(name, options));
+        }
+    }
+
+    let mut body = json!({
+        "model": model,
+        "input": input_items(&chat["messages"])?,
+    });
+
+    for field in ["temperature", "top_p", "stream", "parallel_tool_calls"] {
+        if let Some(value) = chat.get(field) {
+            body[field] = value.clone();
+        }
+    }
+
+    if let Some(max) = chat
+        .get("max_completion_tokens")
+        .or_else(|| chat.get("max_tokens"))
+    {
+        body["max_output_tokens"] = max.clone();
+    }
+
+    if let Some(effort) = chat.get("reasoning_effort") {
+        body["reasoning"] = json!({ "effort": effort });
+    }
+
+    if let Some(format) = chat.get("response_format") {
+        body["text"] = json!({ "format": text_format(format) });
+    }
+
+    let mut tools: Vec<Value> = chat["tools"]
+        .as_array()
+        .map(|tools| tools.iter().map(flatten_function).collect())
+        .unwrap_or_default();
+    tools.extend(options.builtin_tools.iter().cloned());
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
+
+    if let Some(choice) = chat.get("tool_choice") {
+        body["tool_choice"] = flatten_function(choice);
+    }
+
+    if let Some(id) = &options.previous_response_id {
+        body["previous_response_id"] = json!(id);
+    }
+    if let Some(store) = options.store {
+        body["store"] = json!(store);
+    }
+
+    Ok(body)
+}
+
+/// Chat messages as Responses input items: role messages, `function_call`
+/// items for assistant tool calls and `function_call_output` for results
+fn input_items(messages: &Value) -> Result<Vec<Value>, ProviderError> {
+    let mut items = Vec::new();
+
+    for msg in messages.as_array().into_iter().flatten() {
+        match msg["role"].as_str() {
+            Some("tool") => items.push(json!({
+                "type": "function_call_output",
+                "call_id": msg["tool_call_id"],
+                "output": msg["content"],
+            })),
+            Some("assistant") => {
+                // Reasoning items can only be replayed by id (previous_response_id)
+                if msg.get("reasoning_content").is_some() || msg.get("reasoning").is_some() {
+                    return Err(invalid_parameter(
+                        "reasoning_history",
+                        "the Responses API cannot replay reasoning text; use strip or previous_response_id",
+                    ));
+                }
+                if let Some(text) = msg["content"].as_str().filter(|t| !t.is_empty()) {
+                    items.push(json!({ "role": "assistant", "content": text }));
+                }
+                for call in msg["tool_calls"].as_array().into_iter().flatten() {
+                    items.push(json!({
+                        "type": "function_call",
+                        "call_id": call["id"],
+                        "name": call["function"]["name"],
+                        "arguments": call["function"]["arguments"],
+                    }));
+                }
+            }
+            _ => items.push(json!({
+                "role": msg["role"],
+                "content": input_content(&msg["content"])?,
+            })),
+        }
+    }
+
+    Ok(items)
+}
+
+/// Chat content parts as Responses input content
+fn input_content(content: &Value) -> Result<Value, ProviderError> {
+    let Some(parts) = content.as_array() else {
+        return Ok(content.clone());
+    };
+
+    let mut converted = Vec::new();
+    for part in parts {
+        converted.push(match part["type"].as_str() {
+            Some("text") => json!({ "type": "input_text", "text": part["text"] }),
+            Some("image_url") => {
+                let mut image = json!({
+                    "type": "input_image",
+                    "image_url": part["image_url"]["url"],
+                });
+                if let Some(detail) = part["image_url"].get("detail") {
+                    image["detail"] = detail.clone();
+                }
+                image
+            }
+            Some("file") => {
+                let mut file = part["file"].as_object().cloned().unwrap_or_default();
+                file.insert("type".to_string(), json!("input_file"));
+                Value::Object(file)
+            }
+            Some("input_audio") => {
+                return Err(invalid_parameter(
+                    "input_audio",
+                    "audio input is not supported by the Responses API",
+                ))
+            }
+            _ => part.clone(),
+        });
+    }
+
+    Ok(json!(converted))
+}
+
+/// Chat-style `{"type":"function","function":{...}}` tools and tool choices
+/// are flat in the Responses API; anything else passes through
+fn flatten_function(value: &Value) -> Value {
+    match value["function"].as_object() {
+        Some(function) if value["type"] == "function" => {
+            let mut flat: Map<String, Value> = function.clone();
+            flat.insert("type".to_string(), json!("function"));
+            Value::Object(flat)
+        }
+        _ => value.clone(),
+    }
+}
+
+/// `response_format` as a Responses `text.format`
+fn text_format(format: &Value) -> Value {
+    if format["type"] != "json_schema" {
+        return format.clone();
+    }
+    let schema = &format["json_schema"];
+    let mut flat = json!({
+        "type": "json_schema",
+        "name": schema.get("name").cloned().unwrap_or(json!("response")),
+        "schema": schema["schema"],
+    });
+    if let Some(strict) = schema.get("strict") {
+        flat["strict"] = strict.clone();
+    }
+    flat
+}
+
+// ===== Responses =====
+
+/// Whether a parsed body is a Responses API response object
+pub(crate) fn is_response(json: &Value) -> bool {
+    json["object"] == "response"
+}
+
+/// Map a Responses API response onto a chat choice and usage
+pub(crate) fn parse(
+    json: &Value,
+    body: &str,
+) -> Result<(Choice, Option<OpenAIUsage>), ProviderError> {
+    if json["status"] == "failed" {
+        return Err(failed(json, body));
+    }
+
+    let mut content: Option<String> = None;
+    let mut reasoning: Option<String> = None;
+    let mut refusal: Option<String> = None;
+    let mut tool_calls = Vec::new();
+
+    for item in json["output"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    match part["type"].as_str() {
+                        Some("output_text") => append(&mut content, &part["text"]),
+                        Some("refusal") => append(&mut refusal, &part["refusal"]),
+                        _ => {}
+                    }
+                }
+            }
+            Some("reasoning") => {
+                // Summaries are what is exposed; raw text only on some models
+                let parts = item["summary"]
+                    .as_array()
+                    .filter(|s| !s.is_empty())
+                    .or_else(|| item["content"].as_array());
+                for part in parts.into_iter().flatten() {
+                    append(&mut reasoning, &part["text"]);
+                }
+            }
+            Some("function_call") => tool_calls.push(OpenAIToolCall {
+                id: item["call_id"].as_str().unwrap_or("").to_string(),
+                call_type: "function".to_string(),
+                function: FunctionCall {
+                    name: item["name"].as_str().unwrap_or("").to_string(),
+                    arguments: item["arguments"].as_str().unwrap_or("").to_string(),
+                },
+            }),
+            _ => {}
+        }
+    }
+
+    let choice = Choice {
+        finish_reason: finish_reason(json),
+        message: ResponseMessage {
+            role: "assistant".to_string(),
+            content,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            reasoning_content: reasoning,
+            refusal,
+            function_call: None,
+        },
+    };
+    Ok((choice, usage(&json["usage"])))
+}
+
+fn append(target: &mut Option<String>, text: &Value) {
+    if let Some(text) = text.as_str() {
+        target.get_or_insert_with(String::new).push_str(text);
+    }
+}
+
+/// Chat-style finish reason from the response status
+fn finish_reason(response: &Value) -> Option<String> {
+    match response["status"].as_str()? {
+        "completed" => {
+            let called = response["output"]
+                .as_array()
+                .is_some_and(|items| items.iter().any(|i| i["type"] == "function_call"));
+            Some(if called { "tool_calls" } else { "stop" }.to_string())
+        }
+        "incomplete" => Some(
+            match response["incomplete_details"]["reason"].as_str() {
+                Some("max_output_tokens") | None => "length",
+                Some(reason) => reason,
+            }
+            .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+/// Responses usage (`input_tokens`/`output_tokens`) as chat usage
+fn usage(usage: &Value) -> Option<OpenAIUsage> {
+    let input = usage["input_tokens"].as_u64()? as u32;
+    let output = usage["output_tokens"].as_u64().unwrap_or(0) as u32;
+    Some(OpenAIUsage {
+        prompt_tokens: input,
+        completion_tokens: output,
+        total_tokens: usage["total_tokens"]
+            .as_u64()
+            .map(|t| t as u32)
+            .unwrap_or(input + output),
+        prompt_tokens_details: Some(PromptTokensDetails {
+            cached_tokens: usage["input_tokens_details"]["cached_tokens"]
+                .as_u64()
+                .map(|t| t as u32),
+        }),
+        completion_tokens_details: Some(CompletionTokensDetails {
+            reasoning_tokens: usage["output_tokens_details"]["reasoning_tokens"]
+                .as_u64()
+                .map(|t| t as u32),
+        }),
+        prompt_cache_hit_tokens: None,
+    })
+}
+
+/// Error for a response that finished with `status: failed`
+fn failed(response: &Value, body: &str) -> ProviderError {
+    let error_body = json!({ "error": response["error"] }).to_string();
+    ProviderError {
+        response_body: Some(body.to_string()),
+        ..errors::classify(None, &[], &error_body)
+    }
+}
+
+// ===== Streaming =====
+
+/// Whether a stream payload is a typed Responses API event
+pub(crate) fn is_stream_event(json: &Value) -> bool {
+    json["type"]
+        .as_str()
+        .is_some_and(|t| t.starts_with("response.") || t == "error")
+}
+
+/// Response id carried by a stream event (`response.created` and friends)
+pub(crate) fn stream_response_id(json: &Value) -> Option<String> {
+    json["response"]["id"].as_str().map(str::to_string)
+}
+
+/// Tool-call indices of one Responses API stream: `output_index` counts
+/// every output item (reasoning, messages), so function calls are numbered
+/// in the order they are added instead
+#[derive(Debug, Default)]
+pub(crate) struct ToolCallIndices {
+    /// `output_index` of each function call seen, by tool-call index
+    output_indices: Vec<u64>,
+}
+
+impl ToolCallIndices {
+    /// Tool-call index of the function call at `output_index`
+    fn index(&mut self, output_index: u64) -> u32 {
+        let index = match self.output_indices.iter().position(|&i| i == output_index) {
+            Some(index) => index,
+            None => {
+                self.output_indices.push(output_index);
+                self.output_indices.len() - 1
+            }
+        };
+        index as u32
+    }
+}
+
+/// Deltas for one Responses API stream event. Tool calls are numbered by
+/// `indices` when the caller keeps them; stateless callers get the event's
+/// `output_index`, which is at least stable for the life of the item.
+pub(crate) fn stream_deltas(
+    event: &Value,
+    mut indices: Option<&mut ToolCallIndices>,
+) -> Vec<WitContentDelta> {
+    let text = |key: &str| event[key].as_str().map(str::to_string);
+    let mut call_index = || {
+        let output_index = event["output_index"].as_u64()?;
+        Some(match indices.as_deref_mut() {
+            Some(indices) => indices.index(output_index),
+            None => output_index as u32,
+        })
+    };
+
+    match event["type"].as_str().unwrap_or("") {
+        "response.output_text.delta" => vec![WitContentDelta {
+            content: text("delta"),
+            ..empty_delta("content")
+        }],
+        "response.refusal.delta" => vec![WitContentDelta {
+            content: text("delta"),
+            ..empty_delta("refusal")
+        }],
+        "response.reasoning_summary_text.delta" | "response.reasoning_text.delta" => {
+            vec![WitContentDelta {
+                reasoning: text("delta"),
+                ..empty_delta("reasoning")
+            }]
+        }
+        "response.output_item.added" if event["item"]["type"] == "function_call" => {
+            let item = &event["item"];
+            vec![WitContentDelta {
+                tool_call_index: call_index(),
+                tool_call: Some(WitToolCall {
+                    id: item["call_id"].as_str().unwrap_or("").to_string(),
+                    name: item["name"].as_str().unwrap_or("").to_string(),
+                    arguments: item["arguments"].as_str().unwrap_or("").to_string(),
+                }),
+                ..empty_delta("tool_call")
+            }]
+        }
+        "response.function_call_arguments.delta" => vec![WitContentDelta {
+            tool_call_index: call_index(),
+            tool_call: Some(WitToolCall {
+                id: String::new(),
+                name: String::new(),
+                arguments: text("delta").unwrap_or_default(),
+            }),
+            ..empty_delta("tool_call")
+        }],
+        "response.completed" | "response.incomplete" => {
+            let response = &event["response"];
+            let mut deltas = vec![WitContentDelta {
+                finish_reason: finish_reason(response),
+                ..empty_delta("finish")
+            }];
+            if let Some(usage) = usage(&response["usage"]) {
+                deltas.push(WitContentDelta {
+                    usage: Some(wit_usage(usage)),
+                    ..empty_delta("usage")
+                });
+            }
+            deltas.push(WitContentDelta {
+                finish_reason: finish_reason(response),
+                ..empty_delta("done")
+            });
+            deltas
+        }
+        "response.failed" => vec![WitContentDelta {
+            error: Some(failed(&event["response"], "").message),
+            ..empty_delta("error")
+        }],
+        "error" => vec![WitContentDelta {
+            error: Some(
+                text("message").unwrap_or_else(|| "Responses API stream error".to_string()),
+            ),
+            ..empty_delta("error")
+        }],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_body_rewritten_for_responses() {
+        let chat = json!({
+            "model": "o3-pro",
+            "messages": [
+                { "role": "developer", "content": "Be brief." },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "What is this?" },
+                    { "type": "image_url", "image_url": { "url": "https://example.com/a.png" } }
+                ]},
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "lookup", "arguments": "{}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_1", "content": "a cat" }
+            ],
+            "max_completion_tokens": 800,
+            "reasoning_effort": "high",
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "tools": [{ "type": "function", "function": {
+                "name": "lookup", "description": "Look up", "parameters": { "type": "object" }
+            }}],
+            "tool_choice": { "type": "function", "function": { "name": "lookup" } },
+            "response_format": { "type": "json_schema", "json_schema": {
+                "name": "answer", "schema": { "type": "object" }, "strict": true
+            }}
+        });
+        let options = RequestOptions::parse(Some(
+            r#"{"previous_response_id":"resp_1","store":false,"builtin_tools":[{"type":"web_search"}]}"#,
+        ))
+        .unwrap();
+
+        let body = from_chat_body(&chat, &options).unwrap();
+
+        assert_eq!(
+            body["input"][0],
+            json!({ "role": "developer", "content": "Be brief." })
+        );
+        assert_eq!(body["input"][1]["content"][0]["type"], "input_text");
+        assert_eq!(
+            body["input"][1]["content"][1],
+            json!({ "type": "input_image", "image_url": "https://example.com/a.png" })
+        );
+        assert_eq!(body["input"][2]["type"], "function_call");
+        assert_eq!(body["input"][2]["call_id"], "call_1");
+        assert_eq!(
+            body["input"][3],
+            json!({ "type": "function_call_output", "call_id": "call_1", "output": "a cat" })
+        );
+        assert_eq!(body["max_output_tokens"], 800);
+        assert_eq!(body["reasoning"]["effort"], "high");
+        assert!(body.get("stream_options").is_none());
+        assert!(body.get("messages").is_none());
+        assert_eq!(body["tools"][0]["name"], "lookup");
+        assert_eq!(body["tools"][1]["type"], "web_search");
+        assert_eq!(
+            body["tool_choice"],
+            json!({ "type": "function", "name": "lookup" })
+        );
+        assert_eq!(body["text"]["format"]["name"], "answer");
+        assert_eq!(body["text"]["format"]["strict"], true);
+        assert_eq!(body["previous_response_id"], "resp_1");
+        assert_eq!(body["store"], false);
+    }
+
+    #[test]
+    fn test_azure_body_names_deployment() {
+        let chat = json!({
+            "model": "azure/o3-pro",
+            "messages": [{ "role": "user", "content": "Hi" }]
+        });
+        let options = RequestOptions::parse(Some(
+            r#"{"base_url":"https://my-resource.openai.azure.com"}"#,
+        ))
+        .unwrap();
+        assert_eq!(from_chat_body(&chat, &options).unwrap()["model"], "o3-pro");
+
+        let options = RequestOptions::parse(Some(
+            r#"{"base_url":"https://my-resource.openai.azure.com","azure_deployments":{"azure/o3-pro":"reasoning-prod"}}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            from_chat_body(&chat, &options).unwrap()["model"],
+            "reasoning-prod"
+        );
+
+        // Elsewhere the model is sent as given
+        let body = from_chat_body(&chat, &RequestOptions::default()).unwrap();
+        assert_eq!(body["model"], "azure/o3-pro");
+    }
+
+    #[test]
+    fn test_unsupported_chat_fields_rejected() {
+        let chat = |extra: Value| {
+            let mut chat = json!({
+                "model": "o3-pro",
+                "messages": [{ "role": "user", "content": "Hi" }]
+            });
+            if let (Some(chat), Value::Object(extra)) = (chat.as_object_mut(), extra) {
+                chat.extend(extra);
+            }
+            chat
+        };
+        let options = RequestOptions::default();
+
+        for (extra, name) in [
+            (json!({ "stop": ["\n"] }), "stop"),
+            (json!({ "seed": 7 }), "seed"),
+            (
+                json!({ "messages": [
+                    { "role": "user", "content": "Hi" },
+                    { "role": "assistant", "content": "Hello", "reasoning_content": "Greet back." }
+                ]}),
+                "reasoning_history",
+            ),
+            (
+                json!({ "messages": [{ "role": "user", "content": [
+                    { "type": "input_audio", "input_audio": { "data": "UklGR", "format": "wav" } }
+                ]}]}),
+                "input_audio",
+            ),
+        ] {
+            let err = from_chat_body(&chat(extra), &options).unwrap_err();
+            assert_eq!(err.code, Some("INVALID_PARAMETER".to_string()));
+            assert!(err.message.contains(name), "{}", err.message);
+        }
+    }
+
+    #[test]
+    fn test_incomplete_response_is_length() {
+        let response = json!({
+            "object": "response",
+            "status": "incomplete",
+            "incomplete_details": { "reason": "max_output_tokens" },
+            "output": []
+        });
+        assert_eq!(finish_reason(&response), Some("length".to_string()));
+    }
+
+    #[test]
+    fn test_stream_tool_calls_numbered_from_zero() {
+        let added = |output_index: u64, call_id: &str| {
+            json!({ "type": "response.output_item.added", "output_index": output_index, "item": {
+                "type": "function_call", "call_id": call_id, "name": "read_file", "arguments": ""
+            }})
+        };
+        let arguments = |output_index: u64| json!({ "type": "response.function_call_arguments.delta", "output_index": output_index, "delta": "{}" });
+
+        // Reasoning and a message take output items 0 and 1
+        let mut indices = ToolCallIndices::default();
+        let mut index = |event: Value| stream_deltas(&event, Some(&mut indices))[0].tool_call_index;
+        assert_eq!(index(added(2, "call_a")), Some(0));
+        assert_eq!(index(added(4, "call_b")), Some(1));
+        assert_eq!(index(arguments(2)), Some(0));
+        assert_eq!(index(arguments(4)), Some(1));
+
+        // Without state, the output index is all there is
+        assert_eq!(
+            stream_deltas(&arguments(4), None)[0].tool_call_index,
+            Some(4)
+        );
+    }
+}
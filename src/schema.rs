@@ -0,0 +1,353 @@
+//! Minimal JSON Schema validation for structured outputs
+//!
+//! Covers the subset OpenAI accepts for `json_schema` response formats: `type`,
+//! `enum`, `const`, `properties`, `required`, `additionalProperties`, `items`,
+//! `anyOf`/`oneOf`/`allOf`, local `$ref`s into `$defs`/`definitions`, and the
+//! common string, number and array bounds.
+
+use serde_json::Value;
+
+/// `$ref`s being followed, with the path they were followed at
+type ActiveRefs = Vec<(String, String)>;
+
+/// Validate `value` against `schema`; returns one message per violation,
+/// each prefixed with the JSON pointer of the offending value
+pub(crate) fn validate(value: &Value, schema: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(value, schema, schema, "", &mut Vec::new(), &mut errors);
+    errors
+}
+
+fn check(
+    value: &Value,
+    schema: &Value,
+    root: &Value,
+    path: &str,
+    refs: &mut ActiveRefs,
+    errors: &mut Vec<String>,
+) {
+    let Some(schema_obj) = schema.as_object() else {
+        // `true`/`{}` accept everything, `false` rejects everything
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: no value is allowed here", pointer(path)));
+        }
+        return;
+    };
+
+    if let Some(reference) = schema_obj.get("$ref").and_then(Value::as_str) {
+        // Following the same $ref again at the same value would never end
+        let key = (reference.to_string(), path.to_string());
+        if refs.contains(&key) {
+            errors.push(format!("{}: cyclic $ref {}", pointer(path), reference));
+            return;
+        }
+        match resolve_ref(root, reference) {
+            Some(target) => {
+                refs.push(key);
+                check(value, target, root, path, refs, errors);
+                refs.pop();
+            }
+            None => errors.push(format!("{}: unresolved $ref {}", pointer(path), reference)),
+        }
+        return;
+    }
+
+    if let Some(types) = schema_obj.get("type") {
+        let allowed: Vec<&str> = match types {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{}: expected {}, got {}",
+                pointer(path),
+                allowed.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(options) = schema_obj.get("enum").and_then(Value::as_array) {
+        if !options.contains(value) {
+            errors.push(format!(
+                "{}: value is not one of the allowed values",
+                pointer(path)
+            ));
+        }
+    }
+    if let Some(expected) = schema_obj.get("const") {
+        if expected != value {
+            errors.push(format!("{}: value must equal {}", pointer(path), expected));
+        }
+    }
+
+    check_combinators(value, schema_obj, root, path, refs, errors);
+
+    match value {
+        Value::Object(map) => {
+            let properties = schema_obj.get("properties").and_then(Value::as_object);
+
+            if let Some(required) = schema_obj.get("required").and_then(Value::as_array) {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !map.contains_key(name) {
+                        errors.push(format!(
+                            "{}: missing required property '{}'",
+                            pointer(path),
+                            name
+                        ));
+                    }
+                }
+            }
+
+            for (key, item) in map {
+                let item_path = format!("{}/{}", path, escape(key));
+                match properties.and_then(|p| p.get(key)) {
+                    Some(item_schema) => check(item, item_schema, root, &item_path, refs, errors),
+                    None => match schema_obj.get("additionalProperties") {
+                        Some(Value::Bool(false)) => errors.push(format!(
+                            "{}: additional property '{}' is not allowed",
+                            pointer(path),
+                            key
+                        )),
+                        Some(extra) if extra.is_object() => {
+                            check(item, extra, root, &item_path, refs, errors)
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema_obj.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    let item_path = format!("{}/{}", path, i);
+                    check(item, item_schema, root, &item_path, refs, errors);
+                }
+            }
+            bound(schema_obj, "minItems", path, errors, |min| {
+                items.len() as f64 >= min
+            });
+            bound(schema_obj, "maxItems", path, errors, |max| {
+                items.len() as f64 <= max
+            });
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as f64;
+            bound(schema_obj, "minLength", path, errors, |min| len >= min);
+            bound(schema_obj, "maxLength", path, errors, |max| len <= max);
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            bound(schema_obj, "minimum", path, errors, |min| n >= min);
+            bound(schema_obj, "maximum", path, errors, |max| n <= max);
+            bound(schema_obj, "exclusiveMinimum", path, errors, |min| n > min);
+            bound(schema_obj, "exclusiveMaximum", path, errors, |max| n < max);
+        }
+        _ => {}
+    }
+}
+
+fn check_combinators(
+    value: &Value,
+    schema: &serde_json::Map<String, Value>,
+    root: &Value,
+    path: &str,
+    refs: &mut ActiveRefs,
+    errors: &mut Vec<String>,
+) {
+    let matches = |s: &Value, refs: &mut ActiveRefs| {
+        let mut nested = Vec::new();
+        check(value, s, root, path, refs, &mut nested);
+        nested.is_empty()
+    };
+
+    if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+        for s in all {
+            check(value, s, root, path, refs, errors);
+        }
+    }
+    if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+        if !any.iter().any(|s| matches(s, refs)) {
+            errors.push(format!("{}: value matches none of anyOf", pointer(path)));
+        }
+    }
+    if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+        let count = one.iter().filter(|s| matches(s, refs)).count();
+        if count != 1 {
+            errors.push(format!(
+                "{}: value matches {} of oneOf, expected exactly 1",
+                pointer(path),
+                count
+            ));
+        }
+    }
+}
+
+/// Check a numeric keyword; `ok` receives the keyword's value
+fn bound(
+    schema: &serde_json::Map<String, Value>,
+    keyword: &str,
+    path: &str,
+    errors: &mut Vec<String>,
+    ok: impl Fn(f64) -> bool,
+) {
+    if let Some(limit) = schema.get(keyword).and_then(Value::as_f64) {
+        if !ok(limit) {
+            errors.push(format!("{}: violates {} {}", pointer(path), keyword, limit));
+        }
+    }
+}
+
+/// Resolve `#/$defs/Name` style references within the root schema
+fn resolve_ref<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let path = reference.strip_prefix('#')?;
+    if path.is_empty() {
+        return Some(root);
+    }
+    root.pointer(path)
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Escape an object key as a JSON Pointer segment
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn pointer(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "age": { "type": "integer", "minimum": 0 },
+                "role": { "$ref": "#/$defs/role" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "nickname": { "type": ["string", "null"] }
+            },
+            "required": ["name", "age", "role"],
+            "additionalProperties": false,
+            "$defs": {
+                "role": { "enum": ["admin", "user"] }
+            }
+        })
+    }
+
+    #[test]
+    fn test_valid_value() {
+        let value = json!({
+            "name": "Ada",
+            "age": 36,
+            "role": "admin",
+            "tags": ["math"],
+            "nickname": null
+        });
+        assert!(validate(&value, &person_schema()).is_empty());
+    }
+
+    #[test]
+    fn test_reports_each_violation_with_path() {
+        let value = json!({
+            "name": "",
+            "age": 1.5,
+            "tags": ["ok", 3],
+            "extra": true
+        });
+        let errors = validate(&value, &person_schema());
+
+        assert!(errors.contains(&"/: missing required property 'role'".to_string()));
+        assert!(errors.contains(&"/: additional property 'extra' is not allowed".to_string()));
+        assert!(errors.contains(&"/name: violates minLength 1".to_string()));
+        assert!(errors.contains(&"/age: expected integer, got number".to_string()));
+        assert!(errors.contains(&"/tags/1: expected string, got number".to_string()));
+    }
+
+    #[test]
+    fn test_cyclic_ref_reported() {
+        let schema = json!({
+            "$ref": "#/$defs/a",
+            "$defs": {
+                "a": { "$ref": "#/$defs/b" },
+                "b": { "anyOf": [{ "$ref": "#/$defs/a" }] }
+            }
+        });
+        let errors = validate(&json!(1), &schema);
+        assert!(errors.contains(&"/: value matches none of anyOf".to_string()));
+
+        let schema = json!({ "$ref": "#/$defs/a", "$defs": { "a": { "$ref": "#/$defs/a" } } });
+        assert_eq!(
+            validate(&json!(1), &schema),
+            vec!["/: cyclic $ref #/$defs/a".to_string()]
+        );
+
+        // Recursion that descends into the value is fine
+        let tree = json!({
+            "$ref": "#/$defs/node",
+            "$defs": { "node": {
+                "type": "object",
+                "properties": { "children": { "type": "array", "items": { "$ref": "#/$defs/node" } } }
+            }}
+        });
+        let value =
+            json!({ "children": [{ "children": [] }, { "children": [{ "children": 1 }] }] });
+        assert_eq!(
+            validate(&value, &tree),
+            vec!["/children/1/children/0/children: expected array, got number".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_path_segments_escaped() {
+        let schema = json!({ "additionalProperties": { "type": "integer" } });
+        assert_eq!(
+            validate(&json!({ "a/b~c": "x" }), &schema),
+            vec!["/a~1b~0c: expected integer, got string".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_any_of() {
+        let schema = json!({ "anyOf": [{ "type": "string" }, { "type": "integer" }] });
+        assert!(validate(&json!(3), &schema).is_empty());
+        assert_eq!(
+            validate(&json!(true), &schema),
+            vec!["/: value matches none of anyOf".to_string()]
+        );
+    }
+}
@@ -0,0 +1,1254 @@
+//! Streaming (SSE) support
+//!
+//! All entry points share the same event parsing:
+//! - `parse_chunk`: stateless, one delta per call (`handle-stream-chunk`)
+//! - `parse_chunk_multi`: stateless, every delta in the chunk (`handle-stream-chunk-multi`)
+//! - `begin`/`feed`/`finish`: stateful accumulator that buffers partial lines,
+//!   merges content, reasoning and indexed tool-call fragments, and rebuilds the
+//!   final assistant message through the same conversion as `parse_response`.
+//!   The accumulator also applies the text-level extractions that
+//!   `parse-response-with-options` does: a leading `<think>` block becomes
+//!   reasoning deltas and, in prompt tool mode, `<tool_call>` blocks become
+//!   tool-call deltas, with tags split across chunks handled.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::Value;
+
+use crate::backends::{self, BackendProfile};
+use crate::exports::abk::extension::provider::{
+    AssistantMessage as WitAssistantMessage, ContentDelta as WitContentDelta, ProviderError,
+    ToolCall as WitToolCall, Usage as WitUsage,
+};
+use crate::functions;
+use crate::options::RequestOptions;
+use crate::prompt_tools;
+use crate::tags::{Piece, TagSplitter};
+use crate::{
+    assistant_message, errors, responses, think, wit_usage, Choice, FunctionCall, OpenAIToolCall,
+    OpenAIUsage, ResponseMessage,
+};
+
+/// Open streams kept at once; beginning another drops the oldest, so
+/// streams a host never finished or aborted cannot pile up
+const MAX_STREAMS: usize = 64;
+
+thread_local! {
+    /// Open streams keyed by handle
+    static STREAMS: RefCell<HashMap<u32, StreamAccumulator>> = RefCell::new(HashMap::new());
+    /// Next handle to hand out from `begin`
+    static NEXT_HANDLE: Cell<u32> = const { Cell::new(1) };
+}
+
+// ===== Stateless parsing =====
+
+/// Delta types `handle-stream-chunk` returns: the ones it has always
+/// produced, plus the `usage` of a usage-only final chunk
+const LEGACY_DELTA_TYPES: &[&str] = &[
+    "content",
+    "reasoning",
+    "tool_call",
+    "done",
+    "error",
+    "usage",
+];
+
+/// Parse a single SSE chunk into at most one content delta.
+/// Only the first legacy-typed delta of the first `data:` line is returned,
+/// carrying the event's finish reason; an event with nothing else becomes an
+/// empty content delta so the reason is not lost. Use `parse_chunk_multi` to
+/// keep everything, including `refusal` deltas.
+pub(crate) fn parse_chunk(chunk: &str) -> Option<WitContentDelta> {
+    let data = data_lines(chunk).next()?;
+    let deltas = event_deltas(data, parse_event(data).as_ref(), None);
+    let finish_reason = deltas
+        .iter()
+        .find(|delta| delta.delta_type == "finish")
+        .and_then(|delta| delta.finish_reason.clone());
+
+    let delta = deltas
+        .into_iter()
+        .find(|delta| LEGACY_DELTA_TYPES.contains(&delta.delta_type.as_str()))
+        .or_else(|| {
+            finish_reason.is_some().then(|| WitContentDelta {
+                content: Some(String::new()),
+                ..empty_delta("content")
+            })
+        })?;
+    Some(WitContentDelta {
+        finish_reason: delta.finish_reason.or(finish_reason),
+        ..delta
+    })
+}
+
+/// Parse every `data:` line of an SSE chunk into all the deltas it carries
+pub(crate) fn parse_chunk_multi(chunk: &str) -> Vec<WitContentDelta> {
+    data_lines(chunk)
+        .flat_map(|data| event_deltas(data, parse_event(data).as_ref(), None))
+        .collect()
+}
+
+/// Payloads of the `data:` lines in a chunk, in order
+fn data_lines(chunk: &str) -> impl Iterator<Item = &str> {
+    chunk.lines().filter_map(|line| {
+        let data = line.trim().strip_prefix("data:")?.trim_start();
+        (!data.is_empty()).then_some(data)
+    })
+}
+
+/// JSON of an SSE data payload (`None` for `[DONE]` and malformed data)
+fn parse_event(data: &str) -> Option<Value> {
+    serde_json::from_str(data).ok()
+}
+
+/// Build every delta carried by one SSE data payload, given its parsed
+/// JSON; `calls` numbers the tool calls of a Responses API stream
+fn event_deltas(
+    data: &str,
+    json: Option<&Value>,
+    calls: Option<&mut responses::ToolCallIndices>,
+) -> Vec<WitContentDelta> {
+    // Check for done marker
+    if data == "[DONE]" {
+        return vec![empty_delta("done")];
+    }
+    let Some(json) = json else {
+        return Vec::new();
+    };
+
+    // Responses API streams send typed `response.*` events instead of chunks
+    if responses::is_stream_event(json) {
+        return responses::stream_deltas(json, calls);
+    }
+
+    // Mid-stream error events carry an OpenAI error object instead of choices
+    if errors::is_error_body(json) {
+        return vec![WitContentDelta {
+            error: Some(errors::classify(None, &[], data).message),
+            ..empty_delta("error")
+        }];
+    }
+
+    // Usage arrives on its own final chunk with empty choices
+    // (stream_options.include_usage), or alongside the last choice on some servers
+    let usage_delta = serde_json::from_value::<OpenAIUsage>(json["usage"].clone())
+        .ok()
+        .map(|u| WitContentDelta {
+            usage: Some(wit_usage(u)),
+            ..empty_delta("usage")
+        });
+
+    // Extract from choices array
+    let Some(choice) = json["choices"].get(0) else {
+        return usage_delta.into_iter().collect();
+    };
+    let delta = &choice["delta"];
+
+    // Check finish_reason — if it's an error (not "stop", "tool_calls", or null),
+    // return an error delta so the caller can retry instead of ending the session
+    if let Some(finish_reason) = choice["finish_reason"].as_str() {
+        match finish_reason {
+            "stop" | "tool_calls" | "length" | "content_filter" | "function_call" => {
+                // Normal finish reasons — let processing continue below
+            }
+            _ => {
+                // Abnormal finish reason (e.g., "network_error") — return error
+                return vec![WitContentDelta {
+                    error: Some(format!("finish_reason: {}", finish_reason)),
+                    ..empty_delta("error")
+                }];
+            }
+        }
+    }
+
+    // Debug: log the delta keys to see what fields are present
+    if std::env::var("RUST_LOG")
+        .map(|v| v.to_lowercase().contains("debug"))
+        .unwrap_or(false)
+    {
+        if let Some(obj) = delta.as_object() {
+            let keys: Vec<&str> = obj.keys().map(|s| s.as_str()).collect();
+            if !keys.is_empty() {
+                eprintln!(
+                    "[WASM DEBUG] delta keys: {:?}, finish_reason: {:?}",
+                    keys,
+                    choice["finish_reason"].as_str()
+                );
+            }
+        }
+    }
+
+    let mut deltas = Vec::new();
+
+    // Check for reasoning_content delta (from thinking models like GLM, Qwen)
+    if let Some(reasoning) = delta["reasoning_content"].as_str() {
+        deltas.push(WitContentDelta {
+            reasoning: Some(reasoning.to_string()),
+            ..empty_delta("reasoning")
+        });
+    }
+
+    // Check for content delta (may arrive alongside reasoning)
+    if let Some(content) = delta["content"].as_str() {
+        deltas.push(WitContentDelta {
+            content: Some(content.to_string()),
+            ..empty_delta("content")
+        });
+    }
+
+    // Check for tool call deltas (one per entry for parallel tool calls)
+    if let Some(tool_calls) = delta["tool_calls"].as_array() {
+        for tc in tool_calls {
+            // Extract the index from the tool call (OpenAI sends this)
+            let index = tc["index"].as_u64().map(|i| i as u32);
+            let id = tc["id"].as_str().map(|s| s.to_string());
+            let name = tc["function"]["name"].as_str().map(|s| s.to_string());
+            let arguments = tc["function"]["arguments"].as_str().map(|s| s.to_string());
+
+            // Only report entries with meaningful data
+            if id.is_some() || name.is_some() || arguments.is_some() {
+                deltas.push(WitContentDelta {
+                    tool_call_index: index,
+                    tool_call: Some(WitToolCall {
+                        id: id.unwrap_or_default(),
+                        name: name.unwrap_or_default(),
+                        arguments: arguments.unwrap_or_default(),
+                    }),
+                    ..empty_delta("tool_call")
+                });
+            }
+        }
+    }
+
+    // Legacy function_call deltas: a single call with no id; the stateful
+    // stream synthesizes one once the arguments are complete
+    if let Some(call) = delta["function_call"].as_object() {
+        let name = call.get("name").and_then(Value::as_str);
+        let arguments = call.get("arguments").and_then(Value::as_str);
+        if name.is_some() || arguments.is_some() {
+            deltas.push(WitContentDelta {
+                tool_call_index: Some(0),
+                tool_call: Some(WitToolCall {
+                    id: String::new(),
+                    name: name.unwrap_or_default().to_string(),
+                    arguments: arguments.unwrap_or_default().to_string(),
+                }),
+                ..empty_delta("tool_call")
+            });
+        }
+    }
+
+    // Check for refusal delta (structured outputs / safety refusals)
+    if let Some(refusal) = delta["refusal"].as_str() {
+        deltas.push(WitContentDelta {
+            content: Some(refusal.to_string()),
+            ..empty_delta("refusal")
+        });
+    }
+
+    // Report normal finish reasons so truncation and filtering are visible;
+    // a legacy function call is reported like any other tool call
+    if let Some(finish_reason) = choice["finish_reason"].as_str() {
+        let finish_reason = match finish_reason {
+            "function_call" => "tool_calls",
+            other => other,
+        };
+        deltas.push(WitContentDelta {
+            finish_reason: Some(finish_reason.to_string()),
+            ..empty_delta("finish")
+        });
+    }
+
+    deltas.extend(usage_delta);
+    deltas
+}
+
+/// Empty delta of the given type, for filling with struct update syntax
+pub(crate) fn empty_delta(delta_type: &str) -> WitContentDelta {
+    WitContentDelta {
+        delta_type: delta_type.to_string(),
+        content: None,
+        reasoning: None,
+        tool_call_index: None,
+        tool_call: None,
+        error: None,
+        usage: None,
+        finish_reason: None,
+    }
+}
+
+// ===== Stateful accumulation =====
+
+/// Open a new stream and return its handle
+pub(crate) fn begin(model: String, options: &RequestOptions) -> u32 {
+    let accumulator = StreamAccumulator {
+        backend: Some(backends::select(&model, options)),
+        think_tags: options.think_tags(&model).then(|| {
+            if options.think_opened() {
+                think::LeadingBlock::opened()
+            } else {
+                think::LeadingBlock::default()
+            }
+        }),
+        tool_tags: options
+            .prompt_tools()
+            .then(|| TagSplitter::new(prompt_tools::OPEN_TAG, prompt_tools::CLOSE_TAG)),
+        tool_names: options.tool_names.clone(),
+        ..StreamAccumulator::default()
+    };
+
+    let handle = NEXT_HANDLE.with(|next| {
+        let handle = next.get();
+        next.set(handle.wrapping_add(1).max(1));
+        handle
+    });
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        if streams.len() >= MAX_STREAMS {
+            // Oldest is furthest behind the next handle, across wrap-around
+            let next = NEXT_HANDLE.with(Cell::get);
+            if let Some(oldest) = streams
+                .keys()
+                .copied()
+                .max_by_key(|h| next.wrapping_sub(*h))
+            {
+                streams.remove(&oldest);
+            }
+        }
+        streams.insert(handle, accumulator);
+    });
+    handle
+}
+
+/// Buffer `data` and return deltas for every complete line it finishes
+pub(crate) fn feed(handle: u32, data: String) -> Result<Vec<WitContentDelta>, ProviderError> {
+    STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let stream = streams
+            .get_mut(&handle)
+            .ok_or_else(|| unknown_handle(handle))?;
+        Ok(stream.push(&data))
+    })
+}
+
+/// Release a stream the caller gives up on (cancelled or failed request)
+pub(crate) fn abort(handle: u32) {
+    STREAMS.with(|streams| streams.borrow_mut().remove(&handle));
+}
+
+/// Flush any partial line, release the handle and return the assembled message
+pub(crate) fn finish(handle: u32) -> Result<WitAssistantMessage, ProviderError> {
+    let mut stream = STREAMS
+        .with(|streams| streams.borrow_mut().remove(&handle))
+        .ok_or_else(|| unknown_handle(handle))?;
+
+    let rest = std::mem::take(&mut stream.buffer);
+    stream.process_line(&rest);
+    for delta in stream.flush_think() {
+        for delta in stream.rewrite_tool_calls(delta) {
+            stream.apply(&delta);
+        }
+    }
+    for delta in stream.flush_tags() {
+        stream.apply(&delta);
+    }
+
+    // Streams that end without a finish event
+    stream.identify_tool_calls();
+    let usage = stream.usage.take();
+    let id = stream.id.take();
+    let think = stream.think_tags.is_some();
+    let think_seen = stream.think_seen;
+    let emulated = stream.tool_tags.is_some();
+    let tool_calls_split = stream.tool_calls_split;
+    let tool_names = std::mem::take(&mut stream.tool_names);
+    let backend = stream.backend;
+    let mut choice = stream.into_choice();
+    if think {
+        // Also catches a template-opened block, whose lone </think> streamed as content
+        think::extract_into(&mut choice, false, think_seen);
+    }
+    if emulated {
+        // Fenced JSON calls can only be recognised once the text is complete
+        prompt_tools::extract_into(&mut choice, &tool_names, tool_calls_split);
+    }
+    if let Some(backend) = backend {
+        backend.shape_choice(&mut choice);
+    }
+    Ok(assistant_message(id, choice, usage))
+}
+
+/// Id of the completion (`chatcmpl-...`) or response (`resp_...`) an event belongs to
+fn event_id(json: &Value) -> Option<String> {
+    if responses::is_stream_event(json) {
+        return responses::stream_response_id(json);
+    }
+    json["id"].as_str().map(str::to_string)
+}
+
+fn unknown_handle(handle: u32) -> ProviderError {
+    ProviderError {
+        message: format!("Unknown stream handle: {}", handle),
+        code: Some("INVALID_HANDLE".to_string()),
+        http_status: None,
+        response_body: None,
+        is_retryable: Some(false),
+        retry_after: None,
+    }
+}
+
+/// Accumulated state of one in-flight stream
+#[derive(Debug, Default)]
+struct StreamAccumulator {
+    /// Trailing data that does not yet end in a newline
+    buffer: String,
+    /// Completion or response id, from the first event that carries one
+    id: Option<String>,
+    content: Option<String>,
+    reasoning: Option<String>,
+    refusal: Option<String>,
+    finish_reason: Option<String>,
+    /// Tool calls keyed by their streaming index
+    tool_calls: BTreeMap<u32, PartialToolCall>,
+    /// Latest usage report (the final one is cumulative)
+    usage: Option<WitUsage>,
+    /// Backend the stream comes from
+    backend: Option<&'static BackendProfile>,
+    /// Tool-call numbering of a Responses API stream
+    response_calls: responses::ToolCallIndices,
+    /// Splits a leading `<think>` block out of the content
+    think_tags: Option<think::LeadingBlock>,
+    /// Whether a `<think>` tag was seen
+    think_seen: bool,
+    /// Splits `<tool_call>` blocks out of the content in prompt tool mode
+    tool_tags: Option<TagSplitter>,
+    /// Text of the `<tool_call>` block being received
+    tool_block: String,
+    /// Whether a `<tool_call>` block was turned into calls
+    tool_calls_split: bool,
+    /// Tools the request declared, for prompt tool mode
+    tool_names: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl StreamAccumulator {
+    fn push(&mut self, data: &str) -> Vec<WitContentDelta> {
+        self.buffer.push_str(data);
+
+        let mut deltas = Vec::new();
+        while let Some(pos) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=pos).collect();
+            deltas.extend(self.process_line(&line));
+        }
+        deltas
+    }
+
+    /// Absorb one SSE line into the accumulated state
+    fn process_line(&mut self, line: &str) -> Vec<WitContentDelta> {
+        let mut events = Vec::new();
+        for data in data_lines(line) {
+            let json = parse_event(data);
+            if self.id.is_none() {
+                self.id = json.as_ref().and_then(event_id);
+            }
+            events.extend(event_deltas(
+                data,
+                json.as_ref(),
+                Some(&mut self.response_calls),
+            ));
+        }
+        let mut deltas = Vec::new();
+        for event in events {
+            for mut delta in self.rewrite(event) {
+                // Emulated tool calls arrive as text, so the server reports "stop";
+                // some backends report "stop" for native tool calls too
+                let stop_with_tool_calls = self.tool_tags.is_some()
+                    || self
+                        .backend
+                        .is_some_and(BackendProfile::stop_with_tool_calls);
+                if delta.finish_reason.as_deref() == Some("stop")
+                    && stop_with_tool_calls
+                    && !self.tool_calls.is_empty()
+                {
+                    delta.finish_reason = Some("tool_calls".to_string());
+                }
+                if delta.delta_type == "finish" {
+                    deltas.extend(self.identify_tool_calls());
+                }
+                self.apply(&delta);
+                // The done marker has no payload; carry the finish reason seen earlier
+                if delta.delta_type == "done" {
+                    delta.finish_reason = self.finish_reason.clone();
+                }
+                deltas.push(delta);
+            }
+        }
+        deltas
+    }
+
+    /// Route tagged blocks in content: `<think>` to reasoning deltas, then
+    /// `<tool_call>` to tool-call deltas (prompt tool mode)
+    fn rewrite(&mut self, delta: WitContentDelta) -> Vec<WitContentDelta> {
+        let mut deltas = Vec::new();
+        for delta in self.rewrite_think(delta) {
+            deltas.extend(self.rewrite_tool_calls(delta));
+        }
+        deltas
+    }
+
+    fn rewrite_think(&mut self, delta: WitContentDelta) -> Vec<WitContentDelta> {
+        let Some(splitter) = self.think_tags.as_mut() else {
+            return vec![delta];
+        };
+
+        match delta.delta_type.as_str() {
+            "content" => {
+                let pieces = splitter.push(delta.content.as_deref().unwrap_or(""));
+                self.think_pieces(pieces)
+            }
+            "finish" | "done" => {
+                let mut deltas = self.flush_think();
+                deltas.push(delta);
+                deltas
+            }
+            _ => vec![delta],
+        }
+    }
+
+    /// Emit whatever the think splitter still holds back
+    fn flush_think(&mut self) -> Vec<WitContentDelta> {
+        let pieces = self
+            .think_tags
+            .as_mut()
+            .map(think::LeadingBlock::flush)
+            .unwrap_or_default();
+        self.think_pieces(pieces)
+    }
+
+    fn think_pieces(&mut self, pieces: Vec<Piece>) -> Vec<WitContentDelta> {
+        let mut deltas = Vec::new();
+        for piece in pieces {
+            match piece {
+                Piece::Outside(text) => deltas.push(WitContentDelta {
+                    content: Some(text),
+                    ..empty_delta("content")
+                }),
+                Piece::Inside(text) => {
+                    self.think_seen = true;
+                    deltas.push(WitContentDelta {
+                        reasoning: Some(text),
+                        ..empty_delta("reasoning")
+                    });
+                }
+                Piece::Close => self.think_seen = true,
+            }
+        }
+        deltas
+    }
+
+    fn rewrite_tool_calls(&mut self, delta: WitContentDelta) -> Vec<WitContentDelta> {
+        let Some(splitter) = self.tool_tags.as_mut() else {
+            return vec![delta];
+        };
+
+        match delta.delta_type.as_str() {
+            "content" => {
+                let pieces = splitter.push(delta.content.as_deref().unwrap_or(""));
+                self.tool_pieces(pieces)
+            }
+            "finish" | "done" => {
+                let mut deltas = self.flush_tags();
+                deltas.push(delta);
+                deltas
+            }
+            _ => vec![delta],
+        }
+    }
+
+    /// Emit whatever the tool-call splitter still holds, closing an
+    /// unterminated block
+    fn flush_tags(&mut self) -> Vec<WitContentDelta> {
+        let Some(splitter) = self.tool_tags.as_mut() else {
+            return Vec::new();
+        };
+        let mut pieces = splitter.flush();
+        if splitter.inside() {
+            pieces.push(Piece::Close);
+        }
+        // Start fresh so a later flush emits nothing twice
+        *splitter = TagSplitter::new(prompt_tools::OPEN_TAG, prompt_tools::CLOSE_TAG);
+        self.tool_pieces(pieces)
+    }
+
+    fn tool_pieces(&mut self, pieces: Vec<Piece>) -> Vec<WitContentDelta> {
+        let mut deltas = Vec::new();
+        for piece in pieces {
+            match piece {
+                Piece::Outside(text) => deltas.push(WitContentDelta {
+                    content: Some(text),
+                    ..empty_delta("content")
+                }),
+                Piece::Inside(text) => self.tool_block.push_str(&text),
+                Piece::Close => {
+                    let block = std::mem::take(&mut self.tool_block);
+                    let first = self.tool_calls.len()
+                        + deltas.iter().filter(|d| d.tool_call.is_some()).count();
+                    let calls = prompt_tools::parse_calls(&block, first, &self.tool_names);
+                    self.tool_calls_split |= !calls.is_empty();
+                    if calls.is_empty() {
+                        // Not a call after all: give the text back
+                        deltas.push(WitContentDelta {
+                            content: Some(format!(
+                                "{}{}{}",
+                                prompt_tools::OPEN_TAG,
+                                block,
+                                prompt_tools::CLOSE_TAG
+                            )),
+                            ..empty_delta("content")
+                        });
+                    }
+                    for (offset, call) in calls.into_iter().enumerate() {
+                        deltas.push(WitContentDelta {
+                            tool_call_index: Some((first + offset) as u32),
+                            tool_call: Some(WitToolCall {
+                                id: call.id,
+                                name: call.function.name,
+                                arguments: call.function.arguments,
+                            }),
+                            ..empty_delta("tool_call")
+                        });
+                    }
+                }
+            }
+        }
+        deltas
+    }
+
+    /// Merge one delta into the message being built
+    fn apply(&mut self, delta: &WitContentDelta) {
+        let text = match delta.delta_type.as_str() {
+            "reasoning" => delta.reasoning.as_ref().map(|t| (&mut self.reasoning, t)),
+            "content" => delta.content.as_ref().map(|t| (&mut self.content, t)),
+            "refusal" => delta.content.as_ref().map(|t| (&mut self.refusal, t)),
+            _ => None,
+        };
+        if let Some((target, text)) = text {
+            target.get_or_insert_with(String::new).push_str(text);
+        }
+
+        if let Some(finish_reason) = &delta.finish_reason {
+            self.finish_reason = Some(finish_reason.clone());
+        }
+
+        if let Some(tc) = &delta.tool_call {
+            // Servers that omit the index send one complete call per entry
+            let index = delta
+                .tool_call_index
+                .unwrap_or(self.tool_calls.len() as u32);
+            let call = self.tool_calls.entry(index).or_default();
+
+            if !tc.id.is_empty() {
+                call.id = tc.id.clone();
+            }
+            if !tc.name.is_empty() {
+                call.name = tc.name.clone();
+            }
+            call.arguments.push_str(&tc.arguments);
+        }
+
+        if let Some(usage) = &delta.usage {
+            self.usage = Some(*usage);
+        }
+    }
+
+    /// Give tool calls that arrived without an id (legacy `function_call`)
+    /// the id `parse_response` gives them, returning a delta for each
+    fn identify_tool_calls(&mut self) -> Vec<WitContentDelta> {
+        let completion_id = self.id.as_deref();
+        let mut deltas = Vec::new();
+        for (position, (index, call)) in self.tool_calls.iter_mut().enumerate() {
+            if !call.id.is_empty() {
+                continue;
+            }
+            call.id = functions::call_id(completion_id, position, &call.name, &call.arguments);
+            deltas.push(WitContentDelta {
+                tool_call_index: Some(*index),
+                tool_call: Some(WitToolCall {
+                    id: call.id.clone(),
+                    name: String::new(),
+                    arguments: String::new(),
+                }),
+                ..empty_delta("tool_call")
+            });
+        }
+        deltas
+    }
+
+    fn into_choice(self) -> Choice {
+        let tool_calls: Vec<OpenAIToolCall> = self
+            .tool_calls
+            .into_values()
+            .map(|call| OpenAIToolCall {
+                id: call.id,
+                call_type: "function".to_string(),
+                function: FunctionCall {
+                    name: call.name,
+                    arguments: call.arguments,
+                },
+            })
+            .collect();
+
+        Choice {
+            message: ResponseMessage {
+                role: "assistant".to_string(),
+                content: self.content,
+                tool_calls: if tool_calls.is_empty() {
+                    None
+                } else {
+                    Some(tool_calls)
+                },
+                reasoning_content: self.reasoning,
+                refusal: self.refusal,
+                function_call: None,
+            },
+            finish_reason: self.finish_reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_STREAMS, STREAMS};
+    use crate::exports::abk::extension::provider::Guest as ProviderGuest;
+    use crate::OpenAIProvider;
+    use serde_json::json;
+
+    #[test]
+    fn test_stream_matches_parse_response() {
+        let sse = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"Think\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Checking \"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"weather\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"loc\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"ation\\\":\\\"NYC\\\"}\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        // Feed in awkward pieces to exercise partial-line buffering
+        for piece in sse.as_bytes().chunks(7) {
+            OpenAIProvider::stream_feed(handle, String::from_utf8(piece.to_vec()).unwrap())
+                .unwrap();
+        }
+        let streamed = OpenAIProvider::stream_finish(handle).unwrap();
+
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "Checking weather",
+                    "reasoning_content": "Think",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {
+                            "name": "get_weather",
+                            "arguments": "{\"location\":\"NYC\"}"
+                        }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        });
+        let parsed =
+            OpenAIProvider::parse_response(response.to_string(), "gpt-4o".to_string()).unwrap();
+
+        assert_eq!(streamed, parsed);
+    }
+
+    #[test]
+    fn test_stream_feed_returns_deltas_for_complete_lines() {
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+
+        let deltas = OpenAIProvider::stream_feed(
+            handle,
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel".to_string(),
+        )
+        .unwrap();
+        assert!(deltas.is_empty());
+
+        let deltas =
+            OpenAIProvider::stream_feed(handle, "lo\"}}]}\n\ndata: [DONE]\n\n".to_string())
+                .unwrap();
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].content, Some("Hello".to_string()));
+        assert_eq!(deltas[1].delta_type, "done");
+
+        let message = OpenAIProvider::stream_finish(handle).unwrap();
+        assert_eq!(message.content, Some("Hello".to_string()));
+    }
+
+    #[test]
+    fn test_stream_merges_parallel_tool_calls() {
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        OpenAIProvider::stream_feed(
+            handle,
+            concat!(
+                "data: {\"choices\":[{\"delta\":{\"tool_calls\":[",
+                "{\"index\":0,\"id\":\"call_a\",\"function\":{\"name\":\"read\",\"arguments\":\"{}\"}},",
+                "{\"index\":1,\"id\":\"call_b\",\"function\":{\"name\":\"list\",\"arguments\":\"{\\\"p\"}}",
+                "]}}]}\n",
+                "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":1,\"function\":{\"arguments\":\"\\\":1}\"}}]}}]}",
+            )
+            .to_string(),
+        )
+        .unwrap();
+
+        // The last line has no trailing newline and is flushed by finish
+        let message = OpenAIProvider::stream_finish(handle).unwrap();
+        assert_eq!(message.tool_calls.len(), 2);
+        assert_eq!(message.tool_calls[0].id, "call_a");
+        assert_eq!(message.tool_calls[1].name, "list");
+        assert_eq!(message.tool_calls[1].arguments, "{\"p\":1}");
+    }
+
+    #[test]
+    fn test_stream_unknown_handle() {
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        OpenAIProvider::stream_finish(handle).unwrap();
+
+        let err = OpenAIProvider::stream_finish(handle).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_HANDLE".to_string()));
+        assert!(OpenAIProvider::stream_feed(handle, "data: [DONE]\n".to_string()).is_err());
+
+        // Aborting releases the handle; aborting again is harmless
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        OpenAIProvider::stream_abort(handle);
+        OpenAIProvider::stream_abort(handle);
+        let err = OpenAIProvider::stream_finish(handle).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_HANDLE".to_string()));
+    }
+
+    #[test]
+    fn test_abandoned_streams_are_capped() {
+        let first = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        let handles: Vec<u32> = (0..MAX_STREAMS)
+            .map(|_| OpenAIProvider::stream_begin("gpt-4o".to_string()))
+            .collect();
+
+        assert!(STREAMS.with(|streams| streams.borrow().len()) <= MAX_STREAMS);
+        assert!(OpenAIProvider::stream_finish(first).is_err());
+        for handle in handles {
+            OpenAIProvider::stream_finish(handle).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_multi_keeps_reasoning_and_content() {
+        let chunk = r#"data: {"choices":[{"delta":{"reasoning_content":"hmm","content":"Hi"}}]}"#;
+        let deltas = OpenAIProvider::handle_stream_chunk_multi(chunk.to_string());
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].reasoning, Some("hmm".to_string()));
+        assert_eq!(deltas[1].content, Some("Hi".to_string()));
+    }
+
+    #[test]
+    fn test_multi_keeps_parallel_tool_calls() {
+        let chunk = r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"a","function":{"name":"read","arguments":""}},{"index":1,"id":"b","function":{"name":"list","arguments":""}}]}}]}"#;
+        let deltas = OpenAIProvider::handle_stream_chunk_multi(chunk.to_string());
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].tool_call_index, Some(0));
+        assert_eq!(deltas[1].tool_call_index, Some(1));
+        assert_eq!(deltas[1].tool_call.as_ref().unwrap().name, "list");
+    }
+
+    #[test]
+    fn test_multi_reads_every_data_line() {
+        let chunk = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"b\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let deltas = OpenAIProvider::handle_stream_chunk_multi(chunk.to_string());
+        let types: Vec<&str> = deltas.iter().map(|d| d.delta_type.as_str()).collect();
+        assert_eq!(types, ["content", "content", "done"]);
+        assert_eq!(deltas[1].content, Some("b".to_string()));
+
+        // The single-delta export still reports the first one
+        let first = OpenAIProvider::handle_stream_chunk(chunk.to_string()).unwrap();
+        assert_eq!(first.content, Some("a".to_string()));
+    }
+
+    #[test]
+    fn test_usage_only_chunk() {
+        let chunk = r#"data: {"choices":[],"usage":{"prompt_tokens":10,"completion_tokens":5,"total_tokens":15}}"#;
+        let delta = OpenAIProvider::handle_stream_chunk(chunk.to_string()).unwrap();
+        assert_eq!(delta.delta_type, "usage");
+        assert_eq!(delta.usage.unwrap().total_tokens, 15);
+
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        OpenAIProvider::stream_feed(
+            handle,
+            format!(
+                "data: {{\"choices\":[{{\"delta\":{{\"content\":\"Hi\"}}}}],\"usage\":null}}\n{}\ndata: [DONE]\n",
+                chunk
+            ),
+        )
+        .unwrap();
+        let message = OpenAIProvider::stream_finish(handle).unwrap();
+        assert_eq!(message.usage.unwrap().prompt_tokens, 10);
+    }
+
+    #[test]
+    fn test_error_event() {
+        let chunk = r#"data: {"error":{"message":"Internal error","type":"server_error"}}"#;
+        let delta = OpenAIProvider::handle_stream_chunk(chunk.to_string()).unwrap();
+        assert_eq!(delta.delta_type, "error");
+        assert_eq!(delta.error, Some("Internal error".to_string()));
+    }
+
+    #[test]
+    fn test_finish_reason_reaches_done() {
+        let chunk = r#"data: {"choices":[{"delta":{"content":"cut"},"finish_reason":"length"}]}"#;
+        let deltas = OpenAIProvider::handle_stream_chunk_multi(chunk.to_string());
+        assert_eq!(deltas[0].delta_type, "content");
+        assert_eq!(deltas[1].delta_type, "finish");
+        assert_eq!(deltas[1].finish_reason, Some("length".to_string()));
+
+        // Stateless: [DONE] itself says nothing about why the stream ended
+        let done = OpenAIProvider::handle_stream_chunk_multi("data: [DONE]".to_string());
+        assert_eq!(done[0].finish_reason, None);
+
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        let deltas =
+            OpenAIProvider::stream_feed(handle, format!("{}\n\ndata: [DONE]\n\n", chunk)).unwrap();
+        let done = deltas.last().unwrap();
+        assert_eq!(done.delta_type, "done");
+        assert_eq!(done.finish_reason, Some("length".to_string()));
+
+        let message = OpenAIProvider::stream_finish(handle).unwrap();
+        assert_eq!(message.finish_reason, Some("length".to_string()));
+    }
+
+    #[test]
+    fn test_legacy_chunk_keeps_legacy_types() {
+        let chunk = r#"data: {"choices":[{"delta":{"refusal":"I can't help with that."}}]}"#;
+        assert_eq!(OpenAIProvider::handle_stream_chunk(chunk.to_string()), None);
+        assert!(!OpenAIProvider::handle_stream_chunk_multi(chunk.to_string()).is_empty());
+
+        // A bare finish event still reports its reason, on an empty content delta
+        let chunk = r#"data: {"choices":[{"delta":{},"finish_reason":"stop"}]}"#;
+        let delta = OpenAIProvider::handle_stream_chunk(chunk.to_string()).unwrap();
+        assert_eq!(delta.delta_type, "content");
+        assert_eq!(delta.content, Some(String::new()));
+        assert_eq!(delta.finish_reason, Some("stop".to_string()));
+    }
+
+    #[test]
+    fn test_legacy_chunk_carries_finish_reason() {
+        let chunk = r#"data: {"choices":[{"delta":{"content":"Hi"},"finish_reason":"length"}]}"#;
+        let delta = OpenAIProvider::handle_stream_chunk(chunk.to_string()).unwrap();
+        assert_eq!(delta.delta_type, "content");
+        assert_eq!(delta.content, Some("Hi".to_string()));
+        assert_eq!(delta.finish_reason, Some("length".to_string()));
+    }
+
+    #[test]
+    fn test_stream_refusal() {
+        let handle = OpenAIProvider::stream_begin("gpt-4o".to_string());
+        OpenAIProvider::stream_feed(
+            handle,
+            concat!(
+                "data: {\"choices\":[{\"delta\":{\"refusal\":\"I can't \"}}]}\n",
+                "data: {\"choices\":[{\"delta\":{\"refusal\":\"help.\"},\"finish_reason\":\"stop\"}]}\n",
+            )
+            .to_string(),
+        )
+        .unwrap();
+        let message = OpenAIProvider::stream_finish(handle).unwrap();
+        assert_eq!(message.refusal, Some("I can't help.".to_string()));
+        assert_eq!(message.content, None);
+    }
+
+    #[test]
+    fn test_responses_stream_matches_parse_response() {
+        let events = [
+            json!({ "type": "response.created", "response": { "id": "resp_1", "status": "in_progress" } }),
+            json!({ "type": "response.reasoning_summary_text.delta", "output_index": 0, "delta": "Look up" }),
+            json!({ "type": "response.output_text.delta", "output_index": 1, "delta": "Checking " }),
+            json!({ "type": "response.output_text.delta", "output_index": 1, "delta": "weather" }),
+            json!({ "type": "response.output_item.added", "output_index": 2, "item": {
+                "type": "function_call", "call_id": "call_1", "name": "get_weather", "arguments": ""
+            }}),
+            json!({ "type": "response.function_call_arguments.delta", "output_index": 2, "delta": "{\"location\":" }),
+            json!({ "type": "response.function_call_arguments.delta", "output_index": 2, "delta": "\"NYC\"}" }),
+        ];
+        let response = json!({
+            "id": "resp_1",
+            "object": "response",
+            "status": "completed",
+            "output": [
+                { "type": "reasoning", "summary": [{ "type": "summary_text", "text": "Look up" }] },
+                { "type": "message", "content": [{ "type": "output_text", "text": "Checking weather" }] },
+                { "type": "function_call", "call_id": "call_1", "name": "get_weather",
+                  "arguments": "{\"location\":\"NYC\"}" }
+            ],
+            "usage": { "input_tokens": 10, "output_tokens": 7, "total_tokens": 17 }
+        });
+
+        let mut sse = String::new();
+        for event in events
+            .iter()
+            .chain([&json!({ "type": "response.completed", "response": response })])
+        {
+            sse.push_str(&format!(
+                "event: {}\ndata: {}\n\n",
+                event["type"].as_str().unwrap(),
+                event
+            ));
+        }
+
+        let handle = OpenAIProvider::stream_begin("o3-pro".to_string());
+        let mut deltas = Vec::new();
+        for piece in sse.as_bytes().chunks(11) {
+            deltas.extend(
+                OpenAIProvider::stream_feed(handle, String::from_utf8(piece.to_vec()).unwrap())
+                    .unwrap(),
+            );
+        }
+        let streamed = OpenAIProvider::stream_finish(handle).unwrap();
+        let parsed =
+            OpenAIProvider::parse_response(response.to_string(), "o3-pro".to_string()).unwrap();
+
+        assert_eq!(streamed, parsed);
+        assert_eq!(streamed.id, Some("resp_1".to_string()));
+        assert_eq!(streamed.finish_reason, Some("tool_calls".to_string()));
+        // The first function call is tool call 0, whatever its output_index
+        assert!(deltas
+            .iter()
+            .filter(|d| d.delta_type == "tool_call")
+            .all(|d| d.tool_call_index == Some(0)));
+        let done = deltas.last().unwrap();
+        assert_eq!(done.delta_type, "done");
+    }
+
+    #[test]
+    fn test_prompt_tool_calls_stream_matches_parse() {
+        let options = Some(r#"{"tool_mode":"prompt","tool_names":["get_weather"]}"#.to_string());
+        let text = "Checking.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>";
+
+        let mut sse = String::new();
+        for piece in text.as_bytes().chunks(5) {
+            let chunk = json!({ "choices": [{ "delta": { "content": String::from_utf8(piece.to_vec()).unwrap() } }] });
+            sse.push_str(&format!("data: {}\n\n", chunk));
+        }
+        sse.push_str(
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        );
+
+        let handle =
+            OpenAIProvider::stream_begin_with_options("llama3".to_string(), options.clone())
+                .unwrap();
+        let deltas = OpenAIProvider::stream_feed(handle, sse).unwrap();
+        let streamed = OpenAIProvider::stream_finish(handle).unwrap();
+
+        // No part of the tagged block leaks into content deltas
+        let content: String = deltas
+            .iter()
+            .filter(|d| d.delta_type == "content")
+            .filter_map(|d| d.content.clone())
+            .collect();
+        assert_eq!(content, "Checking.\n");
+        assert!(deltas.iter().any(|d| d.delta_type == "tool_call"));
+
+        let response = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": text },
+                "finish_reason": "stop"
+            }]
+        });
+        let parsed = OpenAIProvider::parse_response_with_options(
+            response.to_string(),
+            "llama3".to_string(),
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(streamed, parsed);
+        assert_eq!(parsed.tool_calls[0].name, "get_weather");
+        assert_eq!(parsed.finish_reason, Some("tool_calls".to_string()));
+    }
+
+    #[test]
+    fn test_think_tags_stream_matches_parse() {
+        let text = "<think>\nTwo plus two.\n</think>\n\nIt is 4.";
+
+        let mut sse = String::new();
+        for piece in text.as_bytes().chunks(3) {
+            let chunk = json!({ "choices": [{ "delta": { "content": String::from_utf8(piece.to_vec()).unwrap() } }] });
+            sse.push_str(&format!("data: {}\n\n", chunk));
+        }
+        sse.push_str(
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        );
+
+        // On by default for QwQ
+        let handle =
+            OpenAIProvider::stream_begin_with_options("qwq-32b".to_string(), None).unwrap();
+        let deltas = OpenAIProvider::stream_feed(handle, sse).unwrap();
+        let streamed = OpenAIProvider::stream_finish(handle).unwrap();
+
+        let reasoning: String = deltas.iter().filter_map(|d| d.reasoning.clone()).collect();
+        assert_eq!(reasoning, "\nTwo plus two.\n");
+        assert!(!deltas
+            .iter()
+            .filter_map(|d| d.content.as_deref())
+            .any(|c| c.contains('<')));
+
+        let response = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": text },
+                "finish_reason": "stop"
+            }]
+        });
+        let parsed = OpenAIProvider::parse_response_with_options(
+            response.to_string(),
+            "qwq-32b".to_string(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(streamed, parsed);
+        assert_eq!(parsed.reasoning, Some("Two plus two.".to_string()));
+        assert_eq!(parsed.content, Some("It is 4.".to_string()));
+
+        // Plain parse-response and other models leave the content alone
+        let plain =
+            OpenAIProvider::parse_response(response.to_string(), "qwq-32b".to_string()).unwrap();
+        assert_eq!(plain.content, Some(text.to_string()));
+        let other = OpenAIProvider::parse_response_with_options(
+            response.to_string(),
+            "gpt-4o".to_string(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(other.content, Some(text.to_string()));
+    }
+
+    #[test]
+    fn test_template_opened_think_streams_as_reasoning() {
+        let options = Some(r#"{"think_opened":true}"#.to_string());
+        let content = |text: &str| {
+            let chunk = json!({ "choices": [{ "delta": { "content": text } }] });
+            format!("data: {}\n\n", chunk)
+        };
+
+        let handle =
+            OpenAIProvider::stream_begin_with_options("qwen3-32b".to_string(), options.clone())
+                .unwrap();
+        // Reasoning is reported before the closing tag arrives
+        let first = OpenAIProvider::stream_feed(handle, content("Two plus two.")).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].delta_type, "reasoning");
+        assert_eq!(first[0].reasoning, Some("Two plus two.".to_string()));
+
+        let rest = OpenAIProvider::stream_feed(
+            handle,
+            content("</think>\n\nIt is 4.")
+                + "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        )
+        .unwrap();
+        let answer: String = rest.iter().filter_map(|d| d.content.clone()).collect();
+        assert_eq!(answer, "\n\nIt is 4.");
+        let streamed = OpenAIProvider::stream_finish(handle).unwrap();
+
+        let response = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": "Two plus two.</think>\n\nIt is 4." },
+                "finish_reason": "stop"
+            }]
+        });
+        let parsed = OpenAIProvider::parse_response_with_options(
+            response.to_string(),
+            "qwen3-32b".to_string(),
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(streamed, parsed);
+        assert_eq!(parsed.reasoning, Some("Two plus two.".to_string()));
+        assert_eq!(parsed.content, Some("It is 4.".to_string()));
+    }
+
+    #[test]
+    fn test_ollama_stream_reports_tool_calls() {
+        let sse = concat!(
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"id\":\"call_1\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"{}\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let options = Some(r#"{"base_url":"http://localhost:11434/v1"}"#.to_string());
+
+        let handle =
+            OpenAIProvider::stream_begin_with_options("llama3.1".to_string(), options).unwrap();
+        let deltas = OpenAIProvider::stream_feed(handle, sse.to_string()).unwrap();
+        let message = OpenAIProvider::stream_finish(handle).unwrap();
+
+        let finish = deltas.iter().find(|d| d.delta_type == "finish").unwrap();
+        assert_eq!(finish.finish_reason, Some("tool_calls".to_string()));
+        assert_eq!(message.finish_reason, Some("tool_calls".to_string()));
+    }
+
+    #[test]
+    fn test_legacy_function_call_stream_matches_parse() {
+        let sse = concat!(
+            "data: {\"id\":\"chatcmpl-9\",\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":null,\"function_call\":{\"name\":\"get_weather\",\"arguments\":\"\"}}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-9\",\"choices\":[{\"delta\":{\"function_call\":{\"arguments\":\"{\\\"city\\\":\"}}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-9\",\"choices\":[{\"delta\":{\"function_call\":{\"arguments\":\"\\\"Paris\\\"}\"}}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-9\",\"choices\":[{\"delta\":{},\"finish_reason\":\"function_call\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let handle = OpenAIProvider::stream_begin("gpt-35-turbo".to_string());
+        let deltas = OpenAIProvider::stream_feed(handle, sse.to_string()).unwrap();
+        let streamed = OpenAIProvider::stream_finish(handle).unwrap();
+
+        let body = json!({
+            "id": "chatcmpl-9",
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "function_call": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+                },
+                "finish_reason": "function_call"
+            }]
+        });
+        let parsed =
+            OpenAIProvider::parse_response(body.to_string(), "gpt-35-turbo".to_string()).unwrap();
+
+        assert_eq!(streamed, parsed);
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert!(parsed.tool_calls[0].id.starts_with("call_"));
+        assert_eq!(parsed.tool_calls[0].arguments, "{\"city\":\"Paris\"}");
+
+        // The id is reported once the arguments are complete, before the finish
+        let ids: Vec<&str> = deltas
+            .iter()
+            .filter_map(|d| d.tool_call.as_ref())
+            .map(|tc| tc.id.as_str())
+            .filter(|id| !id.is_empty())
+            .collect();
+        assert_eq!(ids, vec![parsed.tool_calls[0].id.as_str()]);
+        assert_eq!(parsed.finish_reason, Some("tool_calls".to_string()));
+    }
+}
@@ -0,0 +1,125 @@
+//! Streaming split of text on an open/close tag pair
+//!
+//! Used for inline markup in model output (`<tool_call>...</tool_call>`,
+//! `<think>...</think>`). Tags may arrive split across chunks, so a trailing
+//! partial tag is held back until the next piece decides it.
+
+/// A run of text on one side of the tags, or the end of a tagged block
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Piece {
+    Outside(String),
+    Inside(String),
+    /// A closing tag was seen
+    Close,
+}
+
+#[derive(Debug)]
+pub(crate) struct TagSplitter {
+    open: &'static str,
+    close: &'static str,
+    inside: bool,
+    /// Text not yet emitted because it may begin a tag
+    pending: String,
+}
+
+impl TagSplitter {
+    pub(crate) fn new(open: &'static str, close: &'static str) -> Self {
+        Self {
+            open,
+            close,
+            inside: false,
+            pending: String::new(),
+        }
+    }
+
+    /// Whether the last text seen is inside a tagged block
+    pub(crate) fn inside(&self) -> bool {
+        self.inside
+    }
+
+    /// Split `text`, continuing from earlier pieces
+    pub(crate) fn push(&mut self, text: &str) -> Vec<Piece> {
+        self.pending.push_str(text);
+        let mut pieces = Vec::new();
+
+        loop {
+            let tag = if self.inside { self.close } else { self.open };
+            match self.pending.find(tag) {
+                Some(pos) => {
+                    let before: String = self.pending.drain(..pos + tag.len()).collect();
+                    self.emit(&before[..pos], &mut pieces);
+                    if self.inside {
+                        pieces.push(Piece::Close);
+                    }
+                    self.inside = !self.inside;
+                }
+                None => {
+                    // Keep back the longest suffix that could start the tag
+                    let keep = partial_tag_len(&self.pending, tag);
+                    let ready: String = self.pending.drain(..self.pending.len() - keep).collect();
+                    self.emit(&ready, &mut pieces);
+                    return pieces;
+                }
+            }
+        }
+    }
+
+    /// Emit whatever is still held back
+    pub(crate) fn flush(&mut self) -> Vec<Piece> {
+        let rest = std::mem::take(&mut self.pending);
+        let mut pieces = Vec::new();
+        self.emit(&rest, &mut pieces);
+        pieces
+    }
+
+    fn emit(&self, text: &str, pieces: &mut Vec<Piece>) {
+        if text.is_empty() {
+            return;
+        }
+        pieces.push(if self.inside {
+            Piece::Inside(text.to_string())
+        } else {
+            Piece::Outside(text.to_string())
+        });
+    }
+}
+
+/// Length of the longest suffix of `text` that could start `tag`
+pub(crate) fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&n| {
+            text.len() >= n
+                && text.is_char_boundary(text.len() - n)
+                && tag.starts_with(&text[text.len() - n..])
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_split_across_pushes() {
+        let mut splitter = TagSplitter::new("<think>", "</think>");
+        let mut pieces = Vec::new();
+        for chunk in ["Hi <th", "ink>plan", " it</thi", "nk> done", " <"] {
+            pieces.extend(splitter.push(chunk));
+        }
+        pieces.extend(splitter.flush());
+
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Outside("Hi ".to_string()),
+                Piece::Inside("plan".to_string()),
+                Piece::Inside(" it".to_string()),
+                Piece::Close,
+                Piece::Outside(" done".to_string()),
+                Piece::Outside(" ".to_string()),
+                Piece::Outside("<".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Inline `<think>...</think>` reasoning
+//!
+//! DeepSeek-R1 distills, QwQ and many vLLM deployments put reasoning in the
+//! content instead of `reasoning_content`. Only a block opening the content
+//! is moved to the reasoning, so tags quoted later in an answer (prose, HTML,
+//! code) stay put. Chat templates that open the block in the prompt only emit
+//! the closing tag, so text before a `</think>` that comes before any
+//! `<think>` is reasoning too. When the template is known to open the block,
+//! the reply starts as reasoning, which lets a stream route it before the
+//! closing tag arrives.
+
+use crate::tags::{partial_tag_len, Piece};
+use crate::Choice;
+
+pub(crate) const OPEN_TAG: &str = "<think>";
+pub(crate) const CLOSE_TAG: &str = "</think>";
+
+/// Move a leading `<think>` block from the choice's content to its
+/// reasoning; `opened` when the chat template opened it. When anything moved
+/// (or `split_earlier`, for streams that already routed the block), content
+/// and reasoning are trimmed of the whitespace around them.
+pub(crate) fn extract_into(choice: &mut Choice, opened: bool, split_earlier: bool) {
+    let message = &mut choice.message;
+    let content = message.content.take();
+
+    let (reasoning, rest) = split(content.as_deref().unwrap_or(""), opened);
+    if reasoning.is_none() && !split_earlier {
+        message.content = content;
+        return;
+    }
+
+    if let Some(reasoning) = reasoning {
+        message
+            .reasoning_content
+            .get_or_insert_with(String::new)
+            .push_str(&reasoning);
+    }
+    message.reasoning_content = message
+        .reasoning_content
+        .take()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty());
+
+    let rest = rest.trim();
+    message.content = (!rest.is_empty()).then(|| rest.to_string());
+}
+
+/// Split text into the leading reasoning block (if any) and the remaining
+/// content
+fn split(text: &str, opened: bool) -> (Option<String>, String) {
+    if let Some(block) = text.trim_start().strip_prefix(OPEN_TAG) {
+        return match block.find(CLOSE_TAG) {
+            Some(end) => (
+                Some(block[..end].to_string()),
+                block[end + CLOSE_TAG.len()..].to_string(),
+            ),
+            // Cut off while still thinking
+            None => (Some(block.to_string()), String::new()),
+        };
+    }
+
+    // Opening tag supplied by the chat template
+    if opened {
+        return match text.find(CLOSE_TAG) {
+            Some(close) => (
+                Some(text[..close].to_string()),
+                text[close + CLOSE_TAG.len()..].to_string(),
+            ),
+            None => (Some(text.to_string()), String::new()),
+        };
+    }
+    match (text.find(OPEN_TAG), text.find(CLOSE_TAG)) {
+        (open, Some(close)) if open.is_none_or(|open| open > close) => (
+            Some(text[..close].to_string()),
+            text[close + CLOSE_TAG.len()..].to_string(),
+        ),
+        _ => (None, text.to_string()),
+    }
+}
+
+/// Where a stream is relative to the leading block
+#[derive(Debug, Default, PartialEq)]
+enum Phase {
+    /// Only whitespace or the start of `<think>` so far
+    #[default]
+    Start,
+    Inside,
+    /// Past the block, or there was none: text passes through
+    Done,
+}
+
+/// Streaming counterpart of `split`: routes a `<think>` block opening the
+/// content, with tags split across chunks, and passes everything after it
+/// through untouched. A template-opened block is left to `extract_into`
+/// unless the splitter was made with `opened`.
+#[derive(Debug, Default)]
+pub(crate) struct LeadingBlock {
+    phase: Phase,
+    /// The chat template opened the block: text starts as reasoning
+    opened: bool,
+    /// Text not yet emitted because it may begin a tag
+    pending: String,
+}
+
+impl LeadingBlock {
+    /// Splitter for a reply whose `<think>` the chat template already sent
+    pub(crate) fn opened() -> Self {
+        Self {
+            opened: true,
+            ..Self::default()
+        }
+    }
+
+    /// Split `text`, continuing from earlier pieces
+    pub(crate) fn push(&mut self, text: &str) -> Vec<Piece> {
+        self.pending.push_str(text);
+        let mut pieces = Vec::new();
+
+        loop {
+            match self.phase {
+                Phase::Start => {
+                    let lead = self.pending.trim_start();
+                    if let Some(block) = lead.strip_prefix(OPEN_TAG) {
+                        self.pending = block.to_string();
+                        self.phase = Phase::Inside;
+                    } else if OPEN_TAG.starts_with(lead) {
+                        return pieces;
+                    } else if self.opened {
+                        self.phase = Phase::Inside;
+                    } else {
+                        self.phase = Phase::Done;
+                    }
+                }
+                Phase::Inside => match self.pending.find(CLOSE_TAG) {
+                    Some(end) => {
+                        let reasoning: String = self.pending.drain(..end).collect();
+                        self.pending.drain(..CLOSE_TAG.len());
+                        if !reasoning.is_empty() {
+                            pieces.push(Piece::Inside(reasoning));
+                        }
+                        pieces.push(Piece::Close);
+                        self.phase = Phase::Done;
+                    }
+                    None => {
+                        let keep = partial_tag_len(&self.pending, CLOSE_TAG);
+                        let ready: String =
+                            self.pending.drain(..self.pending.len() - keep).collect();
+                        if !ready.is_empty() {
+                            pieces.push(Piece::Inside(ready));
+                        }
+                        return pieces;
+                    }
+                },
+                Phase::Done => {
+                    pieces.extend(self.flush());
+                    return pieces;
+                }
+            }
+        }
+    }
+
+    /// Emit whatever is still held back
+    pub(crate) fn flush(&mut self) -> Vec<Piece> {
+        let rest = std::mem::take(&mut self.pending);
+        if rest.is_empty() {
+            return Vec::new();
+        }
+        vec![match self.phase {
+            Phase::Inside => Piece::Inside(rest),
+            _ => Piece::Outside(rest),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("<think>\nAdd them.\n</think>\n\n4", false),
+            (Some("\nAdd them.\n".to_string()), "\n\n4".to_string())
+        );
+        // Template-opened block
+        assert_eq!(
+            split("Add them.</think>4", false),
+            (Some("Add them.".to_string()), "4".to_string())
+        );
+        assert_eq!(
+            split("Plain answer", false),
+            (None, "Plain answer".to_string())
+        );
+
+        // Known to be template-opened: cut off while still thinking
+        assert_eq!(
+            split("Add them", true),
+            (Some("Add them".to_string()), String::new())
+        );
+        assert_eq!(
+            split("<think>a</think>b", true),
+            (Some("a".to_string()), "b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_tags_in_answer_are_kept() {
+        for text in [
+            "Wrap the plan in <think>...</think> tags.",
+            "```html\n<think>draft</think>\n```",
+            "Answer first.\n<think>aside</think>\nThen more.",
+        ] {
+            assert_eq!(split(text, false), (None, text.to_string()));
+
+            let mut block = LeadingBlock::default();
+            let mut pieces = block.push(text);
+            pieces.extend(block.flush());
+            assert_eq!(pieces, vec![Piece::Outside(text.to_string())]);
+        }
+
+        // Only the leading block moves
+        assert_eq!(
+            split("<think>a</think>Use <think> tags.", false),
+            (Some("a".to_string()), "Use <think> tags.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_leading_block_across_chunks() {
+        let mut block = LeadingBlock::default();
+        let mut pieces = Vec::new();
+        for chunk in ["\n<th", "ink>plan", " it</thi", "nk>Done <think>", "x"] {
+            pieces.extend(block.push(chunk));
+        }
+        pieces.extend(block.flush());
+
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Inside("plan".to_string()),
+                Piece::Inside(" it".to_string()),
+                Piece::Close,
+                Piece::Outside("Done <think>".to_string()),
+                Piece::Outside("x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_opened_block_across_chunks() {
+        let mut block = LeadingBlock::opened();
+        let mut pieces = Vec::new();
+        for chunk in ["Two plus", " two.</th", "ink>\n4 <think>", "x"] {
+            pieces.extend(block.push(chunk));
+        }
+        pieces.extend(block.flush());
+
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Inside("Two plus".to_string()),
+                Piece::Inside(" two.".to_string()),
+                Piece::Close,
+                Piece::Outside("\n4 <think>".to_string()),
+                Piece::Outside("x".to_string()),
+            ]
+        );
+
+        // A model that sends the opening tag anyway
+        let mut block = LeadingBlock::opened();
+        assert_eq!(
+            block.push("<think>a</think>b"),
+            vec![
+                Piece::Inside("a".to_string()),
+                Piece::Close,
+                Piece::Outside("b".to_string())
+            ]
+        );
+    }
+}
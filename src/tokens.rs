@@ -0,0 +1,447 @@
+//! Local prompt token estimates
+//!
+//! Shipping the cl100k/o200k vocabularies would add megabytes to the component,
+//! so text is split into the chunks the cl100k pre-tokenizer produces and each
+//! chunk is weighed by its shape: word length and case, random-looking runs
+//! (base64, hashes), digit groups, punctuation, and CJK, Hangul or other
+//! scripts. The weights are fitted to real cl100k_base counts; on samples of
+//! prose in eight languages, code, JSON, URLs, identifiers and encoded data
+//! the estimate stays within 20% of them. o200k_base (GPT-4o and later) counts
+//! Latin-script text and code about the same but needs up to 40% fewer tokens
+//! for CJK, Korean and Cyrillic, which is overcounted for those models.
+//!
+//! Framing follows OpenAI's accounting: every message costs three tokens
+//! plus one for a name, and the reply is primed with three more. Images are
+//! charged at the fixed low-detail rate, or the rate of a 1024x1024 image.
+
+use serde_json::Value;
+
+use crate::messages::{self, HistoryStyle};
+
+/// Tokens framing each message
+const PER_MESSAGE: u32 = 3;
+/// Extra tokens for a message `name`
+const PER_NAME: u32 = 1;
+/// Tokens priming the assistant reply
+pub(crate) const REPLY_PRIMING: u32 = 3;
+/// Tokens framing each tool call in assistant history
+const PER_TOOL_CALL: u32 = 3;
+/// Tokens framing each tool definition, and the tool section as a whole
+const PER_TOOL: u32 = 8;
+const TOOLS_SECTION: u32 = 12;
+/// Image cost at `detail: low`, and otherwise (a 1024x1024 image: 85 + 4 tiles)
+const IMAGE_LOW: u32 = 85;
+const IMAGE_DEFAULT: u32 = 765;
+
+/// Estimated tokens of each InternalMessage once converted with `style`
+pub(crate) fn per_message(messages: &[Value], style: &HistoryStyle) -> Vec<u32> {
+    let mut tokens = vec![0; messages.len()];
+    for (index, msg) in messages::to_openai_messages_indexed(messages.to_vec(), style) {
+        tokens[index] += message_tokens(&msg);
+    }
+    tokens
+}
+
+/// Estimated tokens of one converted OpenAI message, including framing
+fn message_tokens(msg: &Value) -> u32 {
+    let mut tokens = PER_MESSAGE + content_tokens(&msg["content"]);
+
+    for key in ["reasoning_content", "reasoning", "tool_call_id"] {
+        if let Some(text) = msg[key].as_str() {
+            tokens += text_tokens(text);
+        }
+    }
+    if let Some(name) = msg["name"].as_str() {
+        tokens += PER_NAME + text_tokens(name);
+    }
+    for call in msg["tool_calls"].as_array().into_iter().flatten() {
+        tokens += PER_TOOL_CALL
+            + text_tokens(call["id"].as_str().unwrap_or(""))
+            + text_tokens(call["function"]["name"].as_str().unwrap_or(""))
+            + text_tokens(call["function"]["arguments"].as_str().unwrap_or(""));
+    }
+    tokens
+}
+
+/// Estimated tokens of tool definitions (`{name, description, parameters}`)
+pub(crate) fn tools_tokens(tools: &[Value]) -> u32 {
+    if tools.is_empty() {
+        return 0;
+    }
+    let definitions: u32 = tools
+        .iter()
+        .map(|tool| {
+            PER_TOOL
+                + text_tokens(tool["name"].as_str().unwrap_or(""))
+                + text_tokens(tool["description"].as_str().unwrap_or(""))
+                + match &tool["parameters"] {
+                    Value::Null => 0,
+                    Value::String(schema) => text_tokens(schema),
+                    schema => text_tokens(&schema.to_string()),
+                }
+        })
+        .sum();
+    TOOLS_SECTION + definitions
+}
+
+fn content_tokens(content: &Value) -> u32 {
+    match content {
+        Value::String(text) => text_tokens(text),
+        Value::Array(parts) => parts
+            .iter()
+            .map(|part| match part["type"].as_str() {
+                Some("text") => text_tokens(part["text"].as_str().unwrap_or("")),
+                Some("image_url") if part["image_url"]["detail"] == "low" => IMAGE_LOW,
+                Some("image_url") => IMAGE_DEFAULT,
+                // Audio and files are billed by the server from the media itself
+                _ => 0,
+            })
+            .sum(),
+        _ => 0,
+    }
+}
+
+// Text weights, fitted to cl100k_base counts of English, Spanish, German,
+// French, Russian, Chinese, Japanese and Korean prose, Rust, Python, JSON,
+// Markdown, URLs, identifiers, base64, hex, UUIDs and numbers
+
+/// Letters a lowercase or capitalized word carries in its first token, after
+/// a space and otherwise, and the tokens per letter beyond that
+const WORD_AFTER_SPACE: f64 = 8.0;
+const WORD: f64 = 4.0;
+const PER_EXTRA_LETTER: f64 = 0.15;
+/// Same for a run of capitals
+const CAPITALS: f64 = 4.0;
+const PER_EXTRA_CAPITAL: f64 = 0.3;
+/// Tokens of a capitalized word inside `camelCase`
+const CAMEL_SEGMENT: f64 = 0.8;
+/// Extra tokens for an accented Latin letter, which usually splits the word
+const PER_ACCENT: f64 = 1.0;
+/// Tokens per letter of a random-looking run (base64, hashes, ids)
+const PER_RANDOM_LETTER: f64 = 0.75;
+/// Lowercase consonants in a row that mark a run as random
+const RANDOM_CONSONANTS: usize = 6;
+/// Tokens per CJK character, Hangul syllable and letter of other scripts
+/// (Cyrillic, Greek, ...), plus a base cost per word in those scripts
+const PER_CJK: f64 = 1.1;
+const PER_HANGUL: f64 = 1.0;
+const PER_SCRIPT_LETTER: f64 = 0.35;
+const SCRIPT_WORD: f64 = 0.5;
+/// Extra tokens when `/` or `-` leads a word, which they join only at times
+const LOOSE_LEAD: f64 = 0.4;
+/// Punctuation characters one token covers, and tokens per character past
+/// that; a repeated character (`----`) weighs much less
+const PUNCT: f64 = 3.0;
+const PER_PUNCT: f64 = 0.45;
+const REPEATED_PUNCT: f64 = 0.1;
+/// Tokens per UTF-8 byte of emoji and other non-ASCII symbols
+const PER_SYMBOL_BYTE: f64 = 0.5;
+
+/// What precedes a run of letters in its pre-tokenizer chunk
+#[derive(Clone, Copy, PartialEq)]
+enum Lead {
+    None,
+    Space,
+    Punct(char),
+}
+
+/// Estimated tokens of plain text
+pub(crate) fn text_tokens(text: &str) -> u32 {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = 0.0;
+    // Whether the previous whitespace left its last space to this chunk
+    let mut space_led = false;
+    let mut i = 0;
+
+    // Chunks follow the cl100k pre-tokenizer: contractions, letters with one
+    // leading character, digits in threes, punctuation with its trailing
+    // newlines, and whitespace
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            let end = run_end(&chars, i, char::is_whitespace);
+            let (cost, joins) = space_tokens(&chars[i..end], chars.get(end).copied());
+            tokens += cost;
+            space_led = joins;
+            i = end;
+            continue;
+        }
+
+        let led = std::mem::take(&mut space_led);
+        if let Some(len) = contraction_len(&chars[i..]) {
+            tokens += 1.0;
+            i += len;
+        } else if c.is_alphabetic() {
+            let end = run_end(&chars, i, char::is_alphabetic);
+            let lead = if led { Lead::Space } else { Lead::None };
+            tokens += letter_tokens(&chars[i..end], lead);
+            i = end;
+        } else if c.is_numeric() {
+            let end = run_end(&chars, i, char::is_numeric);
+            tokens += (end - i).div_ceil(3) as f64;
+            i = end;
+        } else if !led && chars.get(i + 1).is_some_and(|c| c.is_alphabetic()) {
+            let end = run_end(&chars, i + 1, char::is_alphabetic);
+            tokens += letter_tokens(&chars[i + 1..end], Lead::Punct(c));
+            i = end;
+        } else {
+            let end = run_end(&chars, i, |c| {
+                !c.is_whitespace() && !c.is_alphabetic() && !c.is_numeric()
+            });
+            tokens += punct_tokens(&chars[i..end]);
+            i = run_end(&chars, end, |c| c == '\n' || c == '\r');
+        }
+    }
+    tokens.round() as u32
+}
+
+/// Index where the run of `f` characters starting at `start` ends
+fn run_end(chars: &[char], start: usize, f: impl Fn(char) -> bool) -> usize {
+    chars[start..]
+        .iter()
+        .position(|&c| !f(c))
+        .map_or(chars.len(), |pos| start + pos)
+}
+
+/// Length of an English contraction (`'s`, `'re`, ...) at the start of `chars`
+fn contraction_len(chars: &[char]) -> Option<usize> {
+    if chars.first() != Some(&'\'') {
+        return None;
+    }
+    let lower = |i: usize| chars.get(i).map(char::to_ascii_lowercase);
+    match (lower(1), lower(2)) {
+        (Some('r'), Some('e')) | (Some('v'), Some('e')) | (Some('l'), Some('l')) => Some(3),
+        (Some('s' | 't' | 'm' | 'd'), _) => Some(2),
+        _ => None,
+    }
+}
+
+/// Tokens of a whitespace run, and whether its last space joins the next chunk
+fn space_tokens(run: &[char], next: Option<char>) -> (f64, bool) {
+    // Everything up to the last newline is one token
+    let (mut tokens, rest) = match run.iter().rposition(|&c| c == '\n' || c == '\r') {
+        Some(pos) => (1.0, &run[pos + 1..]),
+        None => (0.0, run),
+    };
+    let Some(&last) = rest.last() else {
+        return (tokens, false);
+    };
+
+    let joins = match next {
+        Some(next) if next.is_alphabetic() => true,
+        Some(next) if !next.is_numeric() => last == ' ',
+        _ => false,
+    };
+    if joins {
+        tokens += if rest.len() > 1 { 1.0 } else { 0.0 };
+    } else {
+        tokens += if rest.len() > 1 && next.is_some() {
+            2.0
+        } else {
+            1.0
+        };
+    }
+    (tokens, joins)
+}
+
+/// Tokens of a run of punctuation and symbols
+fn punct_tokens(run: &[char]) -> f64 {
+    let mut ascii = 0.0;
+    let mut symbols = 0.0;
+    for (k, &c) in run.iter().enumerate() {
+        if k > 0 && run[k - 1] == c {
+            ascii += REPEATED_PUNCT;
+        } else if c.is_ascii() {
+            ascii += 1.0;
+        } else {
+            symbols += PER_SYMBOL_BYTE * c.len_utf8() as f64;
+        }
+    }
+    let ascii = if ascii == 0.0 {
+        0.0
+    } else if ascii <= PUNCT {
+        1.0
+    } else {
+        ascii * PER_PUNCT
+    };
+    (ascii + symbols).max(1.0)
+}
+
+/// Tokens of a run of letters
+fn letter_tokens(run: &[char], lead: Lead) -> f64 {
+    if run.iter().all(|&c| is_latin(c)) {
+        let accents = run.iter().filter(|c| !c.is_ascii()).count();
+        return word_tokens(run, lead) + accents as f64 * PER_ACCENT;
+    }
+
+    let letters: f64 = run
+        .iter()
+        .map(|&c| {
+            if is_cjk(c) {
+                PER_CJK
+            } else if is_hangul(c) {
+                PER_HANGUL
+            } else {
+                PER_SCRIPT_LETTER
+            }
+        })
+        .sum();
+    letters + SCRIPT_WORD
+}
+
+/// Tokens of a run of Latin letters
+fn word_tokens(run: &[char], lead: Lead) -> f64 {
+    let mut tokens = match lead {
+        // Punctuation that usually merges with the word that follows
+        Lead::Punct('.' | '_' | '(' | '\'' | '\\' | '@' | '<') | Lead::None | Lead::Space => 0.0,
+        Lead::Punct('-' | '/') => LOOSE_LEAD,
+        Lead::Punct(_) => 1.0,
+    };
+
+    // Case segments: `camelCase` is two, a run of capitals one (`HTTPServer`
+    // is `HTTP` and `Server`)
+    let mut segments = Vec::new();
+    let mut i = 0;
+    while i < run.len() {
+        let upper = run_end(run, i, char::is_uppercase) - i;
+        let lower = run_end(run, i + upper, |c| !c.is_uppercase()) - i - upper;
+        if upper > 1 && lower > 0 {
+            segments.push((upper - 1, 0));
+            segments.push((1, lower));
+        } else {
+            segments.push((upper, lower));
+        }
+        i += upper + lower;
+    }
+
+    let mut consonants = 0;
+    let mut longest = 0;
+    for c in run {
+        if !c.is_ascii_lowercase() || "aeiouy".contains(*c) {
+            consonants = 0;
+        } else {
+            consonants += 1;
+            longest = longest.max(consonants);
+        }
+    }
+    // Short case segments or long consonant runs do not occur in words
+    if (segments.len() >= 3 && run.len() < segments.len() * 3) || longest >= RANDOM_CONSONANTS {
+        return tokens + run.len() as f64 * PER_RANDOM_LETTER;
+    }
+
+    for (k, &(upper, lower)) in segments.iter().enumerate() {
+        let len = (upper + lower) as f64;
+        tokens += if lower == 0 {
+            1.0 + (len - CAPITALS).max(0.0) * PER_EXTRA_CAPITAL
+        } else {
+            let (base, free) = if k > 0 {
+                (CAMEL_SEGMENT, WORD_AFTER_SPACE)
+            } else if lead == Lead::Space {
+                (1.0, WORD_AFTER_SPACE)
+            } else {
+                (1.0, WORD)
+            };
+            base + (len - free).max(0.0) * PER_EXTRA_LETTER
+        };
+    }
+    tokens
+}
+
+/// Basic and extended Latin letters
+fn is_latin(c: char) -> bool {
+    c < '\u{0250}'
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'     // Hiragana, Katakana
+        | '\u{3400}'..='\u{4dbf}'   // CJK Extension A
+        | '\u{4e00}'..='\u{9fff}'   // CJK Unified Ideographs
+        | '\u{f900}'..='\u{faff}') // CJK Compatibility Ideographs
+}
+
+fn is_hangul(c: char) -> bool {
+    matches!(c, '\u{ac00}'..='\u{d7af}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Samples with their cl100k_base and o200k_base token counts (tiktoken)
+    const SAMPLES: &[(&str, &str, u32, u32)] = &[
+        ("prose", "Could you summarize the attached quarterly report? I'm mostly interested in revenue growth, the churn numbers for enterprise customers, and whether the team thinks the new pricing tiers are working. Keep it under five bullet points, and flag anything that looks unusual compared to last year.", 53, 52),
+        ("spanish", "¿Podrías revisar el contrato antes del viernes? Necesitamos confirmar las cláusulas de rescisión y la fecha de entrega, porque el cliente pidió cambios en la última reunión.", 46, 36),
+        ("german", "Bitte bereiten Sie die Unterlagen für die Besprechung nächste Woche vor. Die Umsatzentwicklung und der Fortschritt des neuen Projekts sollen übersichtlich dargestellt werden.", 43, 33),
+        ("russian", "Пожалуйста, подготовьте материалы к совещанию на следующей неделе. Нужно наглядно показать динамику продаж и ход работы над новым проектом.", 61, 33),
+        ("chinese", "请帮我把这段文字翻译成英文，并且保持原来的语气。我们下周会发布新版本，主要修复了登录页面的问题，同时提升了搜索功能的速度。", 58, 42),
+        ("japanese", "来週の会議の資料を準備してください。売上の推移と新しいプロジェクトの進捗について、グラフを使って分かりやすくまとめてほしいです。", 68, 47),
+        ("korean", "다음 주 회의 자료를 준비해 주세요. 매출 추이와 새 프로젝트의 진행 상황을 그래프로 알기 쉽게 정리해 주시면 좋겠습니다.", 62, 35),
+        ("emoji", "Shipped it 🎉🚀 Thanks everyone for the help 🙏 Next up: dark mode ✨ and the flaky tests 😅", 31, 27),
+        ("markdown", "## Installation\n\n1. Install the CLI with `npm install -g widget-cli`.\n2. Run `widget init` in your project folder.\n3. Edit **widget.config.json** and set your API key.\n\n> **Note:** the `--force` flag overwrites existing files.\n", 58, 58),
+        ("rust", "impl Config {\n    pub fn from_env() -> Result<Self, ConfigError> {\n        let port = std::env::var(\"PORT\")\n            .unwrap_or_else(|_| \"8080\".to_string())\n            .parse::<u16>()\n            .map_err(ConfigError::InvalidPort)?;\n        Ok(Self { port, verbose: false })\n    }\n}\n", 73, 73),
+        ("python", "def load_users(path):\n    \"\"\"Read users from a CSV file, skipping inactive accounts.\"\"\"\n    users = []\n    with open(path, newline=\"\") as handle:\n        for row in csv.DictReader(handle):\n            if row[\"status\"] != \"inactive\":\n                users.append(User(id=int(row[\"id\"]), email=row[\"email\"]))\n    return users\n", 71, 71),
+        ("sql", "SELECT o.id, o.created_at, c.email, SUM(li.quantity * li.unit_price) AS total\nFROM orders o\nJOIN customers c ON c.id = o.customer_id\nJOIN line_items li ON li.order_id = o.id\nWHERE o.created_at >= '2024-01-01' AND o.status <> 'cancelled'\nGROUP BY o.id, o.created_at, c.email\nORDER BY total DESC\nLIMIT 50;", 90, 91),
+        ("json", "{\"location\": \"San Francisco, CA\", \"unit\": \"celsius\", \"days\": 3, \"include\": [\"humidity\", \"wind_speed\", \"uv_index\"], \"coordinates\": {\"lat\": 37.7749, \"lon\": -122.4194}}", 57, 57),
+        ("log", "2024-11-03T14:22:07.381Z ERROR [worker-3] request_id=7f3a9c2e-41b8-4d0e-9a6f-2c8b1e5d7a90 upstream timed out after 30000ms (attempt 3/3) host=api.internal:8443 path=/v1/orders/88213", 88, 88),
+        ("urls", "https://docs.example.com/api/v2/reference/chat-completions#streaming\nhttps://github.com/example-org/widget-service/pull/1432/files?diff=split\nhttps://storage.googleapis.com/example-bucket/exports/2024-11-03/report.csv", 55, 55),
+        ("identifiers", "getUserPreferencesById MAX_RETRY_ATTEMPTS XMLHttpRequest parse_config_file HttpServerBuilder DEFAULT_TIMEOUT_MS onWindowResize", 21, 25),
+        ("numbers", "Q3 revenue: $4,218,930.55 (+12.4% YoY); units: 18,402; average order: 229.26; refunds: 1,047 (5.69%).", 46, 46),
+        ("base64", "OQyMfXJHNCzYEA8vb3cNZdZw5Y4DUdiujk9urDQvwjG3sIcW6z/BKJa5YiMXdJQodzPCjui6U721a4gkV31T7MKKcKYcdRChzYkhbKFs/8rqSYdHfobbzLlwRvwuGDhOUdggxcPvgAU6iK45lt5Q6AGGWzaYZU6/", 118, 111),
+        ("hex", "5feceb66ffc86f38d952786c6d696c79c2dbc239dd4e91b46729d73a27fb57e9\n6b86b273ff34fce19d6b804eff5a3f5747ada4eaa22f1d49c01e52ddb7875b4b\nd4735e3a265e16eee03f59718b9b5d03019c07d8b6c51f90da3a666eec13ab35", 116, 118),
+    ];
+
+    #[test]
+    fn test_text_tokens() {
+        assert_eq!(text_tokens(""), 0);
+        // "Hello", ",", " world", "!"
+        assert_eq!(text_tokens("Hello, world!"), 4);
+        // Digits in groups of three
+        assert_eq!(text_tokens("1234567"), 3);
+        assert_eq!(text_tokens("fn main() {}"), 4);
+        assert_eq!(text_tokens("你好世界"), 5);
+    }
+
+    #[test]
+    fn test_text_tokens_track_real_counts() {
+        for (name, text, cl100k, o200k) in SAMPLES {
+            let estimate = text_tokens(text);
+            // Within 20% of cl100k_base and at most 10% under o200k_base
+            assert!(
+                estimate.abs_diff(*cl100k) * 5 <= *cl100k && estimate * 10 >= o200k * 9,
+                "{}: estimated {}, cl100k_base {}, o200k_base {}",
+                name,
+                estimate,
+                cl100k,
+                o200k
+            );
+        }
+    }
+
+    #[test]
+    fn test_message_and_tool_tokens() {
+        let msg = json!({ "role": "user", "content": "Hello, world!" });
+        assert_eq!(message_tokens(&msg), PER_MESSAGE + 4);
+
+        let image = json!({ "role": "user", "content": [
+            { "type": "text", "text": "What is this?" },
+            { "type": "image_url", "image_url": { "url": "data:...", "detail": "low" } }
+        ]});
+        assert_eq!(message_tokens(&image), PER_MESSAGE + 4 + IMAGE_LOW);
+
+        let call = json!({ "role": "assistant", "content": null, "tool_calls": [{
+            "id": "call_1", "type": "function",
+            "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+        }]});
+        assert!(message_tokens(&call) > PER_MESSAGE + PER_TOOL_CALL);
+
+        assert_eq!(tools_tokens(&[]), 0);
+        let tools = [json!({
+            "name": "get_weather",
+            "description": "Get the weather",
+            "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+        })];
+        assert!(tools_tokens(&tools) > TOOLS_SECTION + PER_TOOL);
+    }
+}